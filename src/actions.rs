@@ -0,0 +1,402 @@
+//! Configurable mouse actions for workspace buttons and window icons.
+
+use crate::{ClickFocused, PersistentWorkspace, WorkspaceInfo, workspace_reference};
+use niri_ipc::{Action, WorkspaceReferenceArg};
+use serde::de::{
+    IntoDeserializer,
+    value::{self, StrDeserializer},
+};
+use serde::{Deserialize, Serialize};
+
+/// A mouse interaction that can be bound to an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEvent {
+    Click,
+    ShiftClick,
+    CtrlClick,
+    MiddleClick,
+    RightClick,
+    ScrollUp,
+    ScrollDown,
+}
+
+/// Built-in actions that can be bound by name.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NamedAction {
+    /// Focus the workspace or window
+    Focus,
+    /// Move the focused window to the workspace
+    MoveFocusedWindowHere,
+    /// Close the window, or the active window of the workspace
+    CloseWindow,
+    ToggleOverview,
+    /// Center the window's column, or the focused column
+    CenterColumn,
+}
+
+/// Prefix of shell commands, so that a misspelt action name isn't run as one.
+const COMMAND_PREFIX: &str = "exec:";
+
+/// What to do on a mouse event: a named action, or a shell command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MouseAction {
+    Named(NamedAction),
+    /// Shell command with {id}, {idx}, {name} and {window-id} placeholders
+    Command(String),
+}
+
+impl MouseAction {
+    /// Parses an action name, or a shell command prefixed with "exec:". Returns
+    /// None for anything else, which `validate` reports.
+    pub fn from_user(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Some(command) = value.strip_prefix(COMMAND_PREFIX) {
+            return Some(MouseAction::Command(command.trim_start().to_string()));
+        }
+        let deserializer: StrDeserializer<value::Error> = value.into_deserializer();
+        NamedAction::deserialize(deserializer)
+            .ok()
+            .map(MouseAction::Named)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct UserMouseActions {
+    #[serde(default, rename = "on-click")]
+    on_click: Option<String>,
+    #[serde(default, rename = "on-shift-click")]
+    on_shift_click: Option<String>,
+    #[serde(default, rename = "on-ctrl-click")]
+    on_ctrl_click: Option<String>,
+    #[serde(default, rename = "on-click-middle")]
+    on_click_middle: Option<String>,
+    #[serde(default, rename = "on-click-right")]
+    on_click_right: Option<String>,
+    #[serde(default, rename = "on-scroll-up")]
+    on_scroll_up: Option<String>,
+    #[serde(default, rename = "on-scroll-down")]
+    on_scroll_down: Option<String>,
+    /// Keys that don't match any event, reported by `validate`
+    #[serde(flatten)]
+    pub unknown: std::collections::BTreeMap<String, serde_json::Value>,
+}
+
+impl UserMouseActions {
+    /// Each action along with the key of its event.
+    pub fn iter(&self) -> [(&'static str, &Option<String>); 7] {
+        [
+            ("on-click", &self.on_click),
+            ("on-shift-click", &self.on_shift_click),
+            ("on-ctrl-click", &self.on_ctrl_click),
+            ("on-click-middle", &self.on_click_middle),
+            ("on-click-right", &self.on_click_right),
+            ("on-scroll-up", &self.on_scroll_up),
+            ("on-scroll-down", &self.on_scroll_down),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MouseActions {
+    on_click: Option<MouseAction>,
+    on_shift_click: Option<MouseAction>,
+    on_ctrl_click: Option<MouseAction>,
+    on_click_middle: Option<MouseAction>,
+    on_click_right: Option<MouseAction>,
+    on_scroll_up: Option<MouseAction>,
+    on_scroll_down: Option<MouseAction>,
+}
+
+impl MouseActions {
+    /// Actions for workspace buttons. Clicking focuses the workspace unless
+    /// configured otherwise.
+    pub fn workspace_from_user(user: Option<&UserMouseActions>) -> Self {
+        let mut actions = user.map(Self::from_user).unwrap_or_default();
+        actions
+            .on_click
+            .get_or_insert(MouseAction::Named(NamedAction::Focus));
+        actions
+    }
+
+    /// Actions for window icons. Nothing is bound by default, so events fall
+    /// through to the workspace button.
+    pub fn window_from_user(user: Option<&UserMouseActions>) -> Self {
+        user.map(Self::from_user).unwrap_or_default()
+    }
+
+    fn from_user(user: &UserMouseActions) -> Self {
+        let parse = |value: &Option<String>| value.as_deref().and_then(MouseAction::from_user);
+        Self {
+            on_click: parse(&user.on_click),
+            on_shift_click: parse(&user.on_shift_click),
+            on_ctrl_click: parse(&user.on_ctrl_click),
+            on_click_middle: parse(&user.on_click_middle),
+            on_click_right: parse(&user.on_click_right),
+            on_scroll_up: parse(&user.on_scroll_up),
+            on_scroll_down: parse(&user.on_scroll_down),
+        }
+    }
+
+    pub fn get(&self, event: MouseEvent) -> Option<&MouseAction> {
+        match event {
+            MouseEvent::Click => self.on_click.as_ref(),
+            // Modified clicks fall back to a plain click when not configured
+            MouseEvent::ShiftClick => self.on_shift_click.as_ref().or(self.on_click.as_ref()),
+            MouseEvent::CtrlClick => self.on_ctrl_click.as_ref().or(self.on_click.as_ref()),
+            MouseEvent::MiddleClick => self.on_click_middle.as_ref(),
+            MouseEvent::RightClick => self.on_click_right.as_ref(),
+            MouseEvent::ScrollUp => self.on_scroll_up.as_ref(),
+            MouseEvent::ScrollDown => self.on_scroll_down.as_ref(),
+        }
+    }
+}
+
+/// What an action resolves to for a specific workspace (and window).
+#[derive(Debug, Clone)]
+pub enum Resolved {
+    Niri(Action),
+    Shell(String),
+}
+
+/// Resolves `action` for a workspace button, or for the icon of `window_id`
+/// when set. `previous_workspace` is the workspace `ClickFocused::Previous`
+/// goes back to, the same one that has the `previous` class.
+pub fn resolve(
+    action: &MouseAction,
+    workspace: &WorkspaceInfo,
+    window_id: Option<u64>,
+    persistent_workspaces: &[PersistentWorkspace],
+    click_focused: ClickFocused,
+    previous_workspace: Option<u64>,
+) -> Option<Resolved> {
+    let named = match action {
+        MouseAction::Named(named) => named,
+        MouseAction::Command(command) => {
+            return Some(Resolved::Shell(format_command(
+                command, workspace, window_id,
+            )));
+        }
+    };
+
+    let action = match (named, window_id) {
+        (NamedAction::Focus, Some(id)) => Action::FocusWindow { id },
+        (NamedAction::Focus, None) if workspace.is_focused => match click_focused {
+            ClickFocused::Focus => Action::FocusWorkspace {
+                reference: workspace_reference(workspace),
+            },
+            ClickFocused::ToggleOverview => Action::ToggleOverview {},
+            ClickFocused::Previous => Action::FocusWorkspace {
+                reference: WorkspaceReferenceArg::Id(previous_workspace?),
+            },
+        },
+        (NamedAction::Focus, None) => {
+            // Placeholders don't exist in niri yet, so they run their
+            // configured command instead if there is one
+            let spawn = persistent_workspaces
+                .iter()
+                .filter(|_| workspace.is_placeholder)
+                .find(|p| p.name == workspace.name)
+                .and_then(|p| p.spawn.clone());
+            match spawn {
+                Some(command) => Action::Spawn { command },
+                None => Action::FocusWorkspace {
+                    reference: workspace_reference(workspace),
+                },
+            }
+        }
+        (NamedAction::MoveFocusedWindowHere, _) => Action::MoveWindowToWorkspace {
+            window_id: None,
+            reference: workspace_reference(workspace),
+            focus: true,
+        },
+        (NamedAction::CloseWindow, Some(id)) => Action::CloseWindow { id: Some(id) },
+        (NamedAction::CloseWindow, None) => Action::CloseWindow {
+            id: Some(workspace.active_window_id?),
+        },
+        (NamedAction::ToggleOverview, _) => Action::ToggleOverview {},
+        (NamedAction::CenterColumn, Some(id)) => Action::CenterWindow { id: Some(id) },
+        (NamedAction::CenterColumn, None) => Action::CenterColumn {},
+    };
+    Some(Resolved::Niri(action))
+}
+
+fn format_command(command: &str, workspace: &WorkspaceInfo, window_id: Option<u64>) -> String {
+    crate::substitute(command, |placeholder| {
+        let value = match placeholder {
+            "id" => workspace.id.to_string(),
+            "idx" => workspace.idx.to_string(),
+            "name" => workspace.name.clone(),
+            "window-id" => window_id.map(|id| id.to_string()).unwrap_or_default(),
+            _ => return None,
+        };
+        Some(shell_quote(&value))
+    })
+}
+
+/// Quotes a value so it is substituted into a shell command as a single word.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Runs a resolved action without blocking the UI.
+pub fn run(resolved: Resolved) {
+    match resolved {
+        Resolved::Niri(action) => crate::send_action(action),
+        Resolved::Shell(command) => {
+            std::thread::spawn(move || {
+                match std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(&command)
+                    .status()
+                {
+                    Ok(status) if !status.success() => {
+                        log::warn!("Command `{}` exited with {}", command, status)
+                    }
+                    Ok(_) => {}
+                    Err(err) => log::error!("Failed to run `{}`: {}", command, err),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_workspace() -> WorkspaceInfo {
+        WorkspaceInfo {
+            id: 5,
+            idx: 2,
+            name: "it's".to_string(),
+            active_window_id: Some(9),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_mouse_action_from_user() {
+        assert_eq!(
+            MouseAction::from_user("close-window"),
+            Some(MouseAction::Named(NamedAction::CloseWindow))
+        );
+        assert_eq!(
+            MouseAction::from_user("exec: notify-send {name}"),
+            Some(MouseAction::Command("notify-send {name}".to_string()))
+        );
+        // Misspelt actions aren't run as commands
+        assert_eq!(MouseAction::from_user("focus-workspce"), None);
+        assert_eq!(MouseAction::from_user("notify-send {name}"), None);
+    }
+
+    #[test]
+    fn test_workspace_actions_default_to_focus() {
+        let actions = MouseActions::workspace_from_user(None);
+        let focus = MouseAction::Named(NamedAction::Focus);
+        assert_eq!(actions.get(MouseEvent::Click), Some(&focus));
+        assert_eq!(actions.get(MouseEvent::ShiftClick), Some(&focus));
+        assert_eq!(actions.get(MouseEvent::MiddleClick), None);
+        assert_eq!(
+            MouseActions::window_from_user(None),
+            MouseActions::default()
+        );
+    }
+
+    #[test]
+    fn test_format_command() {
+        let workspace = create_test_workspace();
+        assert_eq!(
+            format_command("echo {id} {idx} {name} {window-id}", &workspace, Some(3)),
+            r"echo '5' '2' 'it'\''s' '3'"
+        );
+
+        // Placeholders in values are not substituted again
+        let workspace = WorkspaceInfo {
+            name: "{window-id} {idx}".to_string(),
+            ..workspace
+        };
+        assert_eq!(
+            format_command("echo {name} {window-id}", &workspace, Some(3)),
+            "echo '{window-id} {idx}' '3'"
+        );
+    }
+
+    #[test]
+    fn test_resolve_close_window() {
+        let workspace = create_test_workspace();
+        let close = MouseAction::Named(NamedAction::CloseWindow);
+        assert!(matches!(
+            resolve(&close, &workspace, Some(3), &[], ClickFocused::Focus, None),
+            Some(Resolved::Niri(Action::CloseWindow { id: Some(3) }))
+        ));
+        assert!(matches!(
+            resolve(&close, &workspace, None, &[], ClickFocused::Focus, None),
+            Some(Resolved::Niri(Action::CloseWindow { id: Some(9) }))
+        ));
+
+        let empty = WorkspaceInfo::default();
+        assert!(resolve(&close, &empty, None, &[], ClickFocused::Focus, None).is_none());
+    }
+
+    #[test]
+    fn test_resolve_click_focused() {
+        let focus = MouseAction::Named(NamedAction::Focus);
+        let mut workspace = create_test_workspace();
+        assert!(matches!(
+            resolve(
+                &focus,
+                &workspace,
+                None,
+                &[],
+                ClickFocused::ToggleOverview,
+                None
+            ),
+            Some(Resolved::Niri(Action::FocusWorkspace { .. }))
+        ));
+
+        workspace.is_focused = true;
+        assert!(matches!(
+            resolve(
+                &focus,
+                &workspace,
+                None,
+                &[],
+                ClickFocused::ToggleOverview,
+                None
+            ),
+            Some(Resolved::Niri(Action::ToggleOverview {}))
+        ));
+        assert!(matches!(
+            resolve(&focus, &workspace, None, &[], ClickFocused::Focus, None),
+            Some(Resolved::Niri(Action::FocusWorkspace { .. }))
+        ));
+        assert!(matches!(
+            resolve(
+                &focus,
+                &workspace,
+                None,
+                &[],
+                ClickFocused::Previous,
+                Some(4)
+            ),
+            Some(Resolved::Niri(Action::FocusWorkspace {
+                reference: WorkspaceReferenceArg::Id(4)
+            }))
+        ));
+        // Without a previous workspace there's nothing to go back to
+        assert!(resolve(&focus, &workspace, None, &[], ClickFocused::Previous, None).is_none());
+        // Icons still focus their window
+        assert!(matches!(
+            resolve(
+                &focus,
+                &workspace,
+                Some(3),
+                &[],
+                ClickFocused::ToggleOverview,
+                None
+            ),
+            Some(Resolved::Niri(Action::FocusWindow { id: 3 }))
+        ));
+    }
+}
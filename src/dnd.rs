@@ -0,0 +1,145 @@
+//! Drag and drop between the widgets of this module. Only drags that start and
+//! end within the same waybar process are accepted.
+
+use waybar_cffi::gtk::{
+    self, DestDefaults, TargetEntry, TargetFlags,
+    gdk::{DragAction, ModifierType},
+    glib::IsA,
+    prelude::WidgetExtManual,
+    traits::WidgetExt,
+};
+
+/// Target for dragging a window icon
+const WINDOW_TARGET: &str = "application/x-niri-workspaces-enhanced-window";
+/// Target for dragging a workspace button
+const WORKSPACE_TARGET: &str = "application/x-niri-workspaces-enhanced-workspace";
+
+// Passed as the `info` of each target, to tell drops apart
+const WINDOW_INFO: u32 = 0;
+const WORKSPACE_INFO: u32 = 1;
+
+/// What is being dragged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    Window(u64),
+    Workspace { id: u64, output: Option<String> },
+}
+
+impl Payload {
+    fn info(&self) -> u32 {
+        match self {
+            Payload::Window(_) => WINDOW_INFO,
+            Payload::Workspace { .. } => WORKSPACE_INFO,
+        }
+    }
+
+    fn target(&self) -> TargetEntry {
+        match self {
+            Payload::Window(_) => window_target(),
+            Payload::Workspace { .. } => workspace_target(),
+        }
+    }
+
+    /// Serializes the payload as "<id>" for windows and "<id>\n<output>" for
+    /// workspaces.
+    fn encode(&self) -> String {
+        match self {
+            Payload::Window(id) => id.to_string(),
+            Payload::Workspace { id, output } => {
+                format!("{}\n{}", id, output.as_deref().unwrap_or_default())
+            }
+        }
+    }
+
+    fn decode(info: u32, data: &[u8]) -> Option<Self> {
+        let data = std::str::from_utf8(data).ok()?;
+        match info {
+            WINDOW_INFO => Some(Payload::Window(data.parse().ok()?)),
+            WORKSPACE_INFO => {
+                let (id, output) = data.split_once('\n')?;
+                Some(Payload::Workspace {
+                    id: id.parse().ok()?,
+                    output: (!output.is_empty()).then(|| output.to_string()),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn window_target() -> TargetEntry {
+    TargetEntry::new(WINDOW_TARGET, TargetFlags::SAME_APP, WINDOW_INFO)
+}
+
+fn workspace_target() -> TargetEntry {
+    TargetEntry::new(WORKSPACE_TARGET, TargetFlags::SAME_APP, WORKSPACE_INFO)
+}
+
+/// Makes `widget` draggable, carrying `payload`.
+pub fn source(widget: &impl IsA<gtk::Widget>, payload: Payload) {
+    widget.drag_source_set(
+        ModifierType::BUTTON1_MASK,
+        &[payload.target()],
+        DragAction::MOVE,
+    );
+    widget.connect_drag_data_get(move |_, _, selection_data, info, _| {
+        if info == payload.info() {
+            selection_data.set(&selection_data.target(), 8, payload.encode().as_bytes());
+        }
+    });
+}
+
+/// Accepts drops on `widget`, calling `on_drop` with what was dropped. Windows
+/// are only accepted if `accept_windows` is set.
+pub fn dest(
+    widget: &impl IsA<gtk::Widget>,
+    accept_windows: bool,
+    on_drop: impl Fn(Payload) + 'static,
+) {
+    let mut targets = vec![workspace_target()];
+    if accept_windows {
+        targets.push(window_target());
+    }
+    widget.drag_dest_set(DestDefaults::ALL, &targets, DragAction::MOVE);
+    widget.connect_drag_data_received(move |_, _, _, _, selection_data, info, _| {
+        match Payload::decode(info, &selection_data.data()) {
+            Some(payload) => on_drop(payload),
+            None => log::warn!("Ignoring invalid drop"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_round_trip() {
+        let payloads = [
+            Payload::Window(42),
+            Payload::Workspace {
+                id: 7,
+                output: Some("DP-1".to_string()),
+            },
+            Payload::Workspace {
+                id: 8,
+                output: None,
+            },
+        ];
+        for payload in payloads {
+            let encoded = payload.encode();
+            assert_eq!(
+                Payload::decode(payload.info(), encoded.as_bytes()),
+                Some(payload)
+            );
+        }
+    }
+
+    #[test]
+    fn test_payload_decode_invalid() {
+        assert_eq!(Payload::decode(WINDOW_INFO, b"not a window"), None);
+        assert_eq!(Payload::decode(WINDOW_INFO, &[0xff, 0xfe]), None);
+        assert_eq!(Payload::decode(WORKSPACE_INFO, b"7"), None);
+        assert_eq!(Payload::decode(99, b"7"), None);
+    }
+}
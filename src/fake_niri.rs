@@ -0,0 +1,355 @@
+//! A fake niri compositor for end-to-end tests: a Unix socket server speaking
+//! niri's JSON protocol, with scripted replies and events.
+
+use niri_ipc::{Action, Event, Reply, Request, Response, Window, Workspace};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+
+#[derive(Debug, Default)]
+struct State {
+    workspaces: Vec<Workspace>,
+    windows: Vec<Window>,
+    /// Actions received, in order
+    actions: Vec<Action>,
+}
+
+pub struct FakeNiri {
+    path: PathBuf,
+    state: Arc<Mutex<State>>,
+    events: Option<mpsc::Sender<Event>>,
+}
+
+impl FakeNiri {
+    /// Starts the server on a new socket, which is removed when the server is
+    /// dropped.
+    pub fn start() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "fake-niri-{}-{}.sock",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let (events_tx, events_rx) = mpsc::channel();
+        let events_rx = Arc::new(Mutex::new(Some(events_rx)));
+        std::thread::spawn({
+            let state = state.clone();
+            move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else { return };
+                    let state = state.clone();
+                    let events_rx = events_rx.clone();
+                    std::thread::spawn(move || serve(stream, &state, &events_rx));
+                }
+            }
+        });
+
+        Self {
+            path,
+            state,
+            events: Some(events_tx),
+        }
+    }
+
+    /// Path of the server's socket, for clients to connect to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set_workspaces(&self, workspaces: Vec<Workspace>) {
+        self.state.lock().unwrap().workspaces = workspaces;
+    }
+
+    pub fn set_windows(&self, windows: Vec<Window>) {
+        self.state.lock().unwrap().windows = windows;
+    }
+
+    /// Actions sent by clients so far.
+    pub fn actions(&self) -> Vec<Action> {
+        self.state.lock().unwrap().actions.clone()
+    }
+
+    /// Sends `event` to the client subscribed to the event stream.
+    pub fn send_event(&self, event: Event) {
+        if let Some(events) = &self.events {
+            events.send(event).unwrap();
+        }
+    }
+
+    /// Ends the event stream, which makes the background task return.
+    pub fn close_events(&mut self) {
+        self.events = None;
+    }
+}
+
+impl Drop for FakeNiri {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Handles one client connection, which sends any number of requests.
+fn serve(
+    stream: UnixStream,
+    state: &Mutex<State>,
+    events_rx: &Mutex<Option<mpsc::Receiver<Event>>>,
+) {
+    let mut writer = stream.try_clone().unwrap();
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(Ok(line)) = lines.next() {
+        let reply: Reply = match serde_json::from_str(&line) {
+            Ok(Request::Workspaces) => Ok(Response::Workspaces(
+                state.lock().unwrap().workspaces.clone(),
+            )),
+            Ok(Request::Windows) => Ok(Response::Windows(state.lock().unwrap().windows.clone())),
+            Ok(Request::Outputs) => Ok(Response::Outputs(HashMap::new())),
+            Ok(Request::Action(action)) => {
+                state.lock().unwrap().actions.push(action);
+                Ok(Response::Handled)
+            }
+            Ok(Request::EventStream) => {
+                let Some(events) = events_rx.lock().unwrap().take() else {
+                    return;
+                };
+                write_line(&mut writer, &Ok::<_, String>(Response::Handled));
+                // Stops when the test closes the stream or the client leaves
+                for event in events {
+                    if !write_line(&mut writer, &event) {
+                        return;
+                    }
+                }
+                return;
+            }
+            Ok(request) => Err(format!("Unsupported request: {:?}", request)),
+            Err(err) => Err(format!("Invalid request: {}", err)),
+        };
+        if !write_line(&mut writer, &reply) {
+            return;
+        }
+    }
+}
+
+fn write_line(writer: &mut UnixStream, value: &impl serde::Serialize) -> bool {
+    let mut line = serde_json::to_string(value).unwrap();
+    line.push('\n');
+    writer.write_all(line.as_bytes()).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Update, UserConfig, WorkspaceInfo, background_task};
+    use std::time::Duration;
+
+    fn workspace(id: u64, idx: u8, is_focused: bool) -> Workspace {
+        Workspace {
+            id,
+            idx,
+            name: None,
+            output: Some("DP-1".to_string()),
+            is_urgent: false,
+            is_active: is_focused,
+            is_focused,
+            active_window_id: None,
+        }
+    }
+
+    fn window(id: u64, app_id: &str, workspace_id: u64, column: usize) -> Window {
+        Window {
+            id,
+            title: None,
+            app_id: Some(app_id.to_string()),
+            pid: None,
+            workspace_id: Some(workspace_id),
+            is_focused: false,
+            is_floating: false,
+            is_urgent: false,
+            layout: niri_ipc::WindowLayout {
+                pos_in_scrolling_layout: Some((column, 1)),
+                tile_size: (100.0, 100.0),
+                window_size: (100, 100),
+                tile_pos_in_workspace_view: None,
+                window_offset_in_tile: (0.0, 0.0),
+            },
+        }
+    }
+
+    fn config() -> Config {
+        let user_config: UserConfig = serde_json::from_str(
+            r#"{ "window-icons": { "firefox": "F", "foot": "T" },
+                 "window-icon-format": { "default": "{icon}" } }"#,
+        )
+        .unwrap();
+        Config::from_user(&user_config)
+    }
+
+    /// Runs the background task against the fake server, returning its
+    /// updates.
+    fn start(niri: &FakeNiri) -> async_channel::Receiver<Update> {
+        let (tx, rx) = async_channel::unbounded();
+        let config = config();
+        let path = niri.path().to_path_buf();
+        std::thread::spawn(move || background_task(config, tx, Some(&path)).unwrap());
+        rx
+    }
+
+    /// Polls `f` for up to five seconds.
+    fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        for _ in 0..500 {
+            if let Some(value) = f() {
+                return value;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Timed out");
+    }
+
+    fn recv(rx: &async_channel::Receiver<Update>) -> Update {
+        wait_for(|| rx.try_recv().ok())
+    }
+
+    fn labels(update: &Update) -> Vec<(u64, String)> {
+        let mut labels: Vec<(u64, String)> = update
+            .workspaces
+            .iter()
+            .map(|info: &WorkspaceInfo| (info.id, info.icons.clone()))
+            .collect();
+        labels.sort();
+        labels
+    }
+
+    #[test]
+    fn test_initial_update() {
+        let mut niri = FakeNiri::start();
+        niri.set_workspaces(vec![workspace(1, 1, true), workspace(2, 2, false)]);
+        niri.set_windows(vec![
+            window(10, "firefox", 1, 1),
+            window(11, "foot", 1, 2),
+            window(12, "foot", 2, 1),
+        ]);
+        let rx = start(&niri);
+
+        let update = recv(&rx);
+        assert_eq!(
+            labels(&update),
+            vec![(1, "F T".to_string()), (2, "T".to_string())]
+        );
+        let focused = update.workspaces.iter().find(|info| info.id == 1).unwrap();
+        assert!(focused.is_focused);
+        assert_eq!(focused.window_count, 2);
+
+        niri.close_events();
+    }
+
+    #[test]
+    fn test_events_trigger_updates() {
+        let mut niri = FakeNiri::start();
+        niri.set_workspaces(vec![workspace(1, 1, true), workspace(2, 2, false)]);
+        let rx = start(&niri);
+        assert_eq!(
+            labels(&recv(&rx)),
+            vec![(1, String::new()), (2, String::new())]
+        );
+
+        niri.set_windows(vec![window(10, "firefox", 2, 1)]);
+        niri.send_event(Event::WindowOpenedOrChanged {
+            window: window(10, "firefox", 2, 1),
+        });
+        assert_eq!(
+            labels(&recv(&rx)),
+            vec![(1, String::new()), (2, "F".to_string())]
+        );
+
+        niri.set_workspaces(vec![workspace(1, 1, false), workspace(2, 2, true)]);
+        niri.send_event(Event::WorkspaceActivated {
+            id: 2,
+            focused: true,
+        });
+        let update = recv(&rx);
+        let previous = update.workspaces.iter().find(|info| info.id == 1).unwrap();
+        assert!(previous.is_previous);
+
+        niri.send_event(Event::OverviewOpenedOrClosed { is_open: true });
+        assert!(recv(&rx).overview_open);
+
+        // Closing the stream ends the background task, closing the channel
+        niri.close_events();
+        wait_for(|| rx.is_closed().then_some(()));
+    }
+
+    #[test]
+    fn test_unchanged_updates_are_skipped() {
+        let mut niri = FakeNiri::start();
+        niri.set_workspaces(vec![workspace(1, 1, true)]);
+        let rx = start(&niri);
+        recv(&rx);
+
+        for _ in 0..10 {
+            niri.send_event(Event::WindowLayoutsChanged {
+                changes: Vec::new(),
+            });
+        }
+        niri.set_windows(vec![window(10, "foot", 1, 1)]);
+        niri.send_event(Event::WindowOpenedOrChanged {
+            window: window(10, "foot", 1, 1),
+        });
+        assert_eq!(labels(&recv(&rx)), vec![(1, "T".to_string())]);
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(rx.is_empty());
+
+        niri.close_events();
+    }
+
+    #[test]
+    fn test_urgency_events_trigger_updates() {
+        let mut niri = FakeNiri::start();
+        niri.set_workspaces(vec![workspace(1, 1, true), workspace(2, 2, false)]);
+        let rx = start(&niri);
+        recv(&rx);
+
+        let mut urgent = workspace(2, 2, false);
+        urgent.is_urgent = true;
+        niri.set_workspaces(vec![workspace(1, 1, true), urgent]);
+        niri.send_event(Event::WorkspaceUrgencyChanged {
+            id: 2,
+            urgent: true,
+        });
+        let update = recv(&rx);
+        assert!(update.workspaces.iter().any(|info| info.is_urgent));
+
+        niri.close_events();
+    }
+
+    #[test]
+    fn test_send_actions() {
+        let niri = FakeNiri::start();
+        let mut socket = niri_ipc::socket::Socket::connect_to(niri.path()).unwrap();
+        crate::send_actions_on(
+            &mut socket,
+            vec![
+                Action::FocusWorkspacePrevious {},
+                Action::CloseWindow { id: Some(10) },
+            ],
+        );
+
+        let actions = wait_for(|| Some(niri.actions()).filter(|actions| actions.len() == 2));
+        assert_eq!(
+            format!("{:?}", actions),
+            format!(
+                "{:?}",
+                [
+                    Action::FocusWorkspacePrevious {},
+                    Action::CloseWindow { id: Some(10) },
+                ]
+            )
+        );
+    }
+}
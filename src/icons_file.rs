@@ -0,0 +1,151 @@
+//! External `window-icons-file`, which is reloaded whenever it changes.
+
+use notify::event::{AccessKind, AccessMode, ModifyKind};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Expands a leading `~/` to the home directory.
+pub fn expand_path(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Reads a mapping from app_id to icon. Files ending in `.toml` are parsed as
+/// TOML, everything else as JSON.
+pub fn load(path: &Path) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    parse(path, &contents)
+}
+
+fn parse(
+    path: &Path,
+    contents: &str,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        Ok(toml::from_str(contents)?)
+    } else {
+        Ok(serde_json::from_str(contents)?)
+    }
+}
+
+/// Blocks until the watch fails, calling `on_change` whenever the file at
+/// `path` is written, replaced or created. The parent directory is watched
+/// rather than the file itself, since editors often save by renaming a new
+/// file over the old one. While the directory doesn't exist, its closest
+/// existing ancestor is watched until it's created.
+pub fn watch(path: &Path, mut on_change: impl FnMut()) -> notify::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Some(file_name) = path.file_name() else {
+        return Err(notify::Error::generic(&format!(
+            "{} is not a file",
+            path.display()
+        )));
+    };
+
+    let (tx, events) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mut watched = closest_existing(dir);
+    watcher.watch(&watched, RecursiveMode::NonRecursive)?;
+    for event in events {
+        let event = event?;
+
+        // The directory or one of its ancestors was created or removed
+        let closest = closest_existing(dir);
+        if closest != watched {
+            let _ = watcher.unwatch(&watched);
+            watcher.watch(&closest, RecursiveMode::NonRecursive)?;
+            watched = closest;
+            // The file may have been created along with its directory
+            if watched == dir && path.is_file() {
+                on_change();
+            }
+            continue;
+        }
+
+        if watched == dir
+            && is_write(&event.kind)
+            && event
+                .paths
+                .iter()
+                .any(|changed| changed.file_name() == Some(file_name))
+        {
+            on_change();
+        }
+    }
+    Ok(())
+}
+
+/// `dir`, or its closest ancestor that exists.
+fn closest_existing(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|ancestor| ancestor.is_dir())
+        .unwrap_or(Path::new("/"))
+        .to_path_buf()
+}
+
+/// Whether an event leaves a complete new version of a file: closing it after
+/// writing, creating it, or renaming it into place.
+fn is_write(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Access(AccessKind::Close(AccessMode::Write))
+            | EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Name(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let json = parse(Path::new("icons.json"), r#"{"Firefox": "F"}"#).unwrap();
+        assert_eq!(json.get("Firefox").map(String::as_str), Some("F"));
+
+        let toml = parse(Path::new("icons.toml"), r#""org.gnome.Nautilus" = "N""#).unwrap();
+        assert_eq!(
+            toml.get("org.gnome.Nautilus").map(String::as_str),
+            Some("N")
+        );
+
+        assert!(parse(Path::new("icons.json"), "{").is_err());
+    }
+
+    #[test]
+    fn test_closest_existing() {
+        let dir = std::env::temp_dir();
+        assert_eq!(closest_existing(&dir), dir);
+        assert_eq!(closest_existing(&dir.join("missing/icons")), dir);
+    }
+
+    #[test]
+    fn test_watch_missing_directory() {
+        let root = std::env::temp_dir().join(format!("icons-file-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir(&root).unwrap();
+        let path = root.join("waybar/icons.json");
+
+        let (tx, changes) = std::sync::mpsc::channel();
+        std::thread::spawn({
+            let path = path.clone();
+            move || watch(&path, || tx.send(()).unwrap())
+        });
+        let timeout = std::time::Duration::from_secs(5);
+        // Gives the watch time to start
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        std::fs::create_dir(root.join("waybar")).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        std::fs::write(&path, "{}").unwrap();
+        changes.recv_timeout(timeout).unwrap();
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
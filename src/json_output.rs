@@ -0,0 +1,303 @@
+//! Output for bars that can't load waybar CFFI modules: one JSON object per
+//! line and update, in the format of waybar's `custom` module with
+//! `"return-type": "json"`. The same lines can be read by other bars, e.g.
+//! with eww's `deflisten`. With `--i3bar`, updates are framed for the i3bar
+//! protocol instead, with one block per workspace.
+
+use crate::preview::{DEFAULT_MODULE, user_config};
+use crate::{
+    Config, IconPiece, UserConfig, WorkspaceInfo, background_task, displayed_workspaces,
+    escape_markup, format_accessible_name, format_workspace_label, overview_config, validate,
+    workspace_label_pieces, workspace_states,
+};
+use serde::Serialize;
+use std::io::Write;
+
+pub const USAGE: &str = "\
+Usage: waybar-niri-workspaces-enhanced [--config <waybar-config>] [--module <name>]
+           [--i3bar]
+
+Prints one JSON line per update for waybar's custom module, or the i3bar
+protocol with --i3bar. The options are read from the module's block in a
+waybar config, like with preview.";
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Output {
+    /// Labels of all shown workspaces, with the focused one in bold
+    text: String,
+    /// Accessible name of each workspace, one per line
+    tooltip: String,
+    class: Vec<&'static str>,
+    /// The individual workspaces, for bars that render their own buttons
+    workspaces: Vec<WorkspaceOutput>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct WorkspaceOutput {
+    id: u64,
+    idx: u8,
+    name: String,
+    output: Option<String>,
+    label: String,
+    class: Vec<&'static str>,
+    /// The window icons in the label, for styling them by state
+    windows: Vec<WindowOutput>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct WindowOutput {
+    id: u64,
+    label: String,
+    class: Vec<String>,
+}
+
+/// A block of the i3bar protocol's status line.
+#[derive(Serialize, Debug, PartialEq)]
+struct I3barBlock {
+    full_text: String,
+    name: &'static str,
+    /// The workspace id, so click events can be told apart
+    instance: String,
+    markup: &'static str,
+    urgent: bool,
+}
+
+/// Runs the module with the command line arguments, printing output for every
+/// update from niri until the connection is lost.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config_path = None;
+    let mut module = DEFAULT_MODULE.to_string();
+    let mut i3bar = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--i3bar" => i3bar = true,
+            "--help" => {
+                println!("{}\n\n{}", USAGE, crate::preview::USAGE);
+                return Ok(());
+            }
+            flag @ ("--config" | "--module") => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", flag))?
+                    .clone();
+                if flag == "--config" {
+                    config_path = Some(value);
+                } else {
+                    module = value;
+                }
+            }
+            _ => return Err(format!("Unexpected argument {}\n\n{}", arg, USAGE).into()),
+        }
+    }
+    let user_config = match config_path {
+        Some(path) => {
+            let waybar_config = std::fs::read_to_string(&path)
+                .map_err(|err| format!("Failed to read {}: {}", path, err))?;
+            user_config(&waybar_config, &module)?
+        }
+        None => UserConfig::default(),
+    };
+
+    for problem in validate::check(&user_config, validate::pango_markup) {
+        log::error!("Invalid config: {}", problem);
+    }
+    let config = Config::from_user(&user_config);
+    let overview_config = overview_config(&config);
+
+    let (tx, rx) = async_channel::unbounded();
+    std::thread::spawn({
+        let config = config.clone();
+        move || {
+            if let Err(err) = background_task(config, tx, None) {
+                log::error!("Background task error: {}", err);
+            }
+        }
+    });
+
+    let mut stdout = std::io::stdout().lock();
+    if i3bar {
+        // The header, followed by an infinite array of status lines
+        writeln!(stdout, "{{\"version\":1}}\n[")?;
+    }
+    let mut first = true;
+    while let Ok(update) = rx.recv_blocking() {
+        let config = match &overview_config {
+            Some(overview_config) if update.overview_open => overview_config,
+            _ => &config,
+        };
+        let ws_info = displayed_workspaces(config, update.workspaces, &update.outputs);
+        let output = render(config, &ws_info, update.overview_open);
+        if i3bar {
+            if !first {
+                write!(stdout, ",")?;
+            }
+            serde_json::to_writer(&mut stdout, &i3bar_blocks(&output))?;
+        } else {
+            serde_json::to_writer(&mut stdout, &output)?;
+        }
+        writeln!(stdout)?;
+        stdout.flush()?;
+        first = false;
+    }
+
+    Err("Lost connection to niri".into())
+}
+
+fn render(config: &Config, ws_info: &[WorkspaceInfo], overview_open: bool) -> Output {
+    let workspaces: Vec<WorkspaceOutput> = ws_info
+        .iter()
+        .map(|info| WorkspaceOutput {
+            id: info.id,
+            idx: info.idx,
+            name: info.name.clone(),
+            output: info.output.clone(),
+            label: format_workspace_label(config, info),
+            class: workspace_states(info),
+            windows: workspace_label_pieces(config, info)
+                .into_iter()
+                .filter_map(|piece| match piece {
+                    IconPiece::Window {
+                        id,
+                        markup,
+                        classes,
+                    } => Some(WindowOutput {
+                        id,
+                        label: markup,
+                        class: classes,
+                    }),
+                    IconPiece::Markup(_) => None,
+                })
+                .collect(),
+        })
+        .collect();
+
+    let text = workspaces
+        .iter()
+        .zip(ws_info)
+        .map(|(workspace, info)| {
+            if info.is_focused {
+                format!("<b>{}</b>", workspace.label)
+            } else {
+                workspace.label.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let tooltip = ws_info
+        .iter()
+        .map(|info| escape_markup(&format_accessible_name(&config.accessible_format, info)))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut class = Vec::new();
+    if overview_open {
+        class.push("overview");
+    }
+    if ws_info.iter().any(|info| info.is_urgent) {
+        class.push("urgent");
+    }
+
+    Output {
+        text,
+        tooltip,
+        class,
+        workspaces,
+    }
+}
+
+/// One block per workspace, with the focused one in bold like in `text`.
+fn i3bar_blocks(output: &Output) -> Vec<I3barBlock> {
+    output
+        .workspaces
+        .iter()
+        .map(|workspace| I3barBlock {
+            full_text: if workspace.class.contains(&"focused") {
+                format!("<b>{}</b>", workspace.label)
+            } else {
+                workspace.label.clone()
+            },
+            name: "niri-workspaces",
+            instance: workspace.id.to_string(),
+            markup: "pango",
+            urgent: workspace.class.contains(&"urgent"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let config = Config::from_user(&UserConfig::default());
+        let ws_info = [
+            WorkspaceInfo {
+                id: 1,
+                idx: 1,
+                is_focused: true,
+                is_active: true,
+                ..Default::default()
+            },
+            WorkspaceInfo {
+                id: 2,
+                idx: 2,
+                name: "chat".to_string(),
+                is_urgent: true,
+                window_count: 1,
+                ..Default::default()
+            },
+        ];
+
+        let output = render(&config, &ws_info, false);
+        assert_eq!(output.text, "<b>1</b> 2 chat");
+        assert_eq!(
+            output.tooltip,
+            "Workspace 1, no windows\nWorkspace 2 chat, 1 window"
+        );
+        assert_eq!(output.class, vec!["urgent"]);
+        assert_eq!(
+            output.workspaces[0].class,
+            vec!["focused", "active", "empty"]
+        );
+        assert_eq!(output.workspaces[1].label, "2 chat");
+
+        assert_eq!(
+            serde_json::to_value(i3bar_blocks(&output)).unwrap(),
+            serde_json::json!([
+                { "full_text": "<b>1</b>", "name": "niri-workspaces", "instance": "1",
+                  "markup": "pango", "urgent": false },
+                { "full_text": "2 chat", "name": "niri-workspaces", "instance": "2",
+                  "markup": "pango", "urgent": true },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_render_window_classes() {
+        let config = Config::from_user(&UserConfig::default());
+        let ws_info = [WorkspaceInfo {
+            id: 1,
+            idx: 1,
+            icons: "T".to_string(),
+            icon_pieces: vec![IconPiece::Window {
+                id: 10,
+                markup: "T".to_string(),
+                classes: vec!["window".to_string(), "urgent".to_string()],
+            }],
+            window_count: 1,
+            ..Default::default()
+        }];
+
+        let output = render(&config, &ws_info, false);
+        assert_eq!(
+            output.workspaces[0].windows,
+            vec![WindowOutput {
+                id: 10,
+                label: "T".to_string(),
+                class: vec!["window".to_string(), "urgent".to_string()],
+            }]
+        );
+    }
+}
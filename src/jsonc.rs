@@ -0,0 +1,98 @@
+//! Waybar's config format: JSON with comments and trailing commas.
+
+/// Converts JSONC to JSON by removing `//` and `/* */` comments and trailing
+/// commas. Anything inside strings is left alone.
+pub fn to_json(text: &str) -> String {
+    remove_trailing_commas(&remove_comments(text))
+}
+
+fn remove_comments(text: &str) -> String {
+    let mut json = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            json.push(c);
+            match c {
+                '\\' => json.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                json.push(c);
+            }
+            ('/', Some('/')) => {
+                // Keep the newline, so line numbers in errors still match
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = None;
+                for c in chars.by_ref() {
+                    if prev == Some('*') && c == '/' {
+                        break;
+                    }
+                    prev = Some(c);
+                }
+                json.push(' ');
+            }
+            _ => json.push(c),
+        }
+    }
+    json
+}
+
+/// Removes commas followed only by whitespace and a closing bracket. Must run
+/// after comments are removed.
+fn remove_trailing_commas(text: &str) -> String {
+    let mut json = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' {
+            let next = text[i + 1..].trim_start().chars().next();
+            if matches!(next, Some('}' | ']')) {
+                continue;
+            }
+        }
+        json.push(c);
+    }
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let jsonc = r#"{
+            // A comment with "quotes", and a trailing comma,
+            "format": "{index} // not a comment", /* inline */
+            "list": ["a,", "b\"/*", ],
+            "object": { "key": 1, },
+        }"#;
+        let value: serde_json::Value = serde_json::from_str(&to_json(jsonc)).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "format": "{index} // not a comment",
+                "list": ["a,", "b\"/*"],
+                "object": { "key": 1 },
+            })
+        );
+    }
+}
@@ -1,17 +1,31 @@
 // General structure of the module was borrowed from https://github.com/LawnGnome/niri-taskbar/blob/main/src/lib.rs
 
+mod actions;
 mod default_icons;
-
+mod dnd;
+#[cfg(test)]
+mod fake_niri;
+mod icons_file;
+pub mod json_output;
+mod jsonc;
+mod minimap;
+pub mod preview;
+mod recording;
+mod validate;
+
+use actions::MouseEvent;
 use niri_ipc::socket::Socket;
 use niri_ipc::{Event, Request, Response, Window};
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
 use waybar_cffi::{
     InitInfo, Module,
     gtk::{
-        self, Button, Label, Orientation,
-        glib::MainContext,
-        prelude::{ButtonExt, LabelExt},
+        self, Button, Label, Orientation, gdk,
+        glib::{self, Cast, ControlFlow, IsA, MainContext, Propagation},
+        prelude::{AtkObjectExt, ButtonExt, LabelExt, ObjectExt, WidgetExtManual},
         traits::{ContainerExt, StyleContextExt, WidgetExt},
     },
     waybar_module,
@@ -19,8 +33,82 @@ use waybar_cffi::{
 
 const DEFAULT_WORKSPACE_FORMAT: &str = "{index-and-name}{separator}{window-icons}";
 const DEFAULT_FORMAT: &str = "{icon}";
-const DEFAULT_FOCUSED_FORMAT: &str = "<span foreground='blue'>{icon}</span>";
-const DEFAULT_URGENT_FORMAT: &str = "<span foreground='red'>{icon}</span>";
+const DEFAULT_ICON_SEPARATOR: &str = " ";
+const DEFAULT_LABEL_SEPARATOR: &str = ": ";
+const DEFAULT_COLUMN_FORMAT: &str = "{icons}";
+const DEFAULT_ACCESSIBLE_FORMAT: &str = "Workspace {index-and-name}, {windows}";
+const DEFAULT_MINIMAP_SCALE: f64 = 0.02;
+const DEFAULT_UPDATE_DEBOUNCE_MS: u64 = 16;
+/// Smooth-scroll distance that fires a scroll action, the same as one step of
+/// a mouse wheel.
+const SMOOTH_SCROLL_STEP: f64 = 1.0;
+
+/// The state a window icon is rendered in. When a window matches more than one
+/// state, the earliest variant wins: urgent, focused, active, floating, default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WindowState {
+    Urgent,
+    Focused,
+    /// The active window of its workspace, but not the globally focused one.
+    Active,
+    Floating,
+    Default,
+}
+
+impl WindowState {
+    fn of(window: &Window, active_window_id: Option<u64>) -> Self {
+        if window.is_urgent {
+            Self::Urgent
+        } else if window.is_focused {
+            Self::Focused
+        } else if active_window_id == Some(window.id) {
+            Self::Active
+        } else if window.is_floating {
+            Self::Floating
+        } else {
+            Self::Default
+        }
+    }
+}
+
+/// CSS classes of a window icon, for styling icons in style.css. Unlike
+/// `WindowState`, any number of the states can apply at once.
+fn window_classes(window: &Window, active_window_id: Option<u64>) -> Vec<String> {
+    let states = [
+        ("focused", window.is_focused),
+        ("urgent", window.is_urgent),
+        ("active", active_window_id == Some(window.id)),
+        ("floating", window.is_floating),
+    ];
+    let mut classes = vec!["window".to_string()];
+    classes.extend(
+        states
+            .into_iter()
+            .filter(|(_, applies)| *applies)
+            .map(|(state, _)| state.to_string()),
+    );
+    if let Some(app_id) = &window.app_id {
+        classes.push(format!("app-{}", css_class_name(app_id)));
+    }
+    classes
+}
+
+/// Escapes text from niri (window titles, app_ids) so it can be substituted
+/// into a pango markup format string.
+fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\'' => escaped.push_str("&apos;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
 
 fn get_raw_icon(cfg: &Config, window: &Window) -> String {
     let Some(app_id) = &window.app_id else {
@@ -39,40 +127,405 @@ fn get_raw_icon(cfg: &Config, window: &Window) -> String {
         })
 }
 
-fn format_icon(cfg: &Config, icon: &str, is_focused: bool, is_urgent: bool) -> String {
-    let format = if is_urgent {
-        &cfg.window_icon_formats.urgent
-    } else if is_focused {
-        &cfg.window_icon_formats.focused
-    } else {
-        &cfg.window_icon_formats.default
+/// Formats a single window icon. `index` is the 1-based position of the window
+/// within its workspace.
+fn format_icon(
+    cfg: &Config,
+    icon: &str,
+    window: &Window,
+    index: usize,
+    state: WindowState,
+) -> String {
+    let formats = &cfg.window_icon_formats;
+    let format = match state {
+        WindowState::Urgent => &formats.urgent,
+        WindowState::Focused => &formats.focused,
+        WindowState::Active => &formats.active,
+        WindowState::Floating => &formats.floating,
+        WindowState::Default => &formats.default,
+    };
+
+    let title = window.title.as_deref().unwrap_or_default();
+    let app_id = window.app_id.as_deref().unwrap_or_default();
+    let pid = window.pid.map(|pid| pid.to_string()).unwrap_or_default();
+
+    substitute(format, |placeholder| match placeholder {
+        "icon" => Some(icon.to_string()),
+        "title" => Some(escape_markup(title)),
+        "app_id" => Some(escape_markup(app_id)),
+        "pid" => Some(pid.clone()),
+        "index" => Some(index.to_string()),
+        _ => None,
+    })
+}
+
+/// Replaces each `{placeholder}` in `format` with `value(placeholder)`, leaving
+/// those it returns `None` for as they are. Substituted text is never scanned
+/// again, so window titles and the like can't inject placeholders.
+fn substitute(format: &str, mut value: impl FnMut(&str) -> Option<String>) -> String {
+    let mut result = String::with_capacity(format.len());
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let replaced = rest[1..]
+            .find('}')
+            .and_then(|end| Some((value(&rest[1..=end])?, end + 2)));
+        match replaced {
+            Some((text, len)) => {
+                result.push_str(&text);
+                rest = &rest[len..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Formats the icons for the windows of a single workspace, which must be
+/// sorted by their position in the scrolling layout. Icons of windows stacked
+/// in the same column are grouped and wrapped with `column-format`, and
+/// floating windows are grouped separately and wrapped with `floating-format`.
+fn format_window_icons(
+    cfg: &Config,
+    windows: &[&Window],
+    active_window_id: Option<u64>,
+) -> Vec<IconPiece> {
+    let (floating, tiled): (Vec<&Window>, Vec<&Window>) =
+        windows.iter().partition(|w| w.is_floating);
+    let ordered = match cfg.floating_position {
+        FloatingPosition::Before => floating.iter().chain(&tiled),
+        FloatingPosition::After => tiled.iter().chain(&floating),
     };
 
-    format.replace("{icon}", icon)
+    // Consecutive windows sharing a column, along with their formatted icons.
+    // All floating windows share a single group.
+    let mut groups: Vec<(IconGroup, Vec<IconPiece>)> = Vec::new();
+    for (i, window) in ordered.enumerate() {
+        let state = WindowState::of(window, active_window_id);
+        let raw_icon = get_raw_icon(cfg, window);
+        let icon = IconPiece::Window {
+            id: window.id,
+            markup: format_icon(cfg, &raw_icon, window, i + 1, state),
+            classes: window_classes(window, active_window_id),
+        };
+
+        let group = if window.is_floating {
+            IconGroup::Floating
+        } else {
+            IconGroup::Column(window.layout.pos_in_scrolling_layout.map(|(col, _)| col))
+        };
+        match groups.last_mut() {
+            Some((prev_group, icons)) if group.joins(prev_group) => icons.push(icon),
+            _ => groups.push((group, vec![icon])),
+        }
+    }
+
+    let mut pieces = Vec::new();
+    for (i, (group, icons)) in groups.into_iter().enumerate() {
+        if i > 0 {
+            push_markup(&mut pieces, &cfg.column_separator);
+        }
+
+        let format = match group {
+            IconGroup::Floating => &cfg.floating_format,
+            IconGroup::Column(Some(_)) if icons.len() > 1 => &cfg.column_format,
+            IconGroup::Column(_) => &cfg.column_format_single,
+        };
+        let count = icons.len().to_string();
+        let replace_count = |text: &str| {
+            substitute(text, |placeholder| {
+                (placeholder == "count").then(|| count.clone())
+            })
+        };
+        let Some((before, after)) = format.split_once("{icons}") else {
+            push_markup(&mut pieces, &replace_count(format));
+            continue;
+        };
+
+        push_markup(&mut pieces, &replace_count(before));
+        for (j, icon) in icons.into_iter().enumerate() {
+            if j > 0 {
+                push_markup(&mut pieces, &cfg.icon_separator);
+            }
+            pieces.push(icon);
+        }
+        push_markup(&mut pieces, &replace_count(after));
+    }
+    pieces
+}
+
+/// A piece of a workspace's rendered window icons. Each window's icon is kept
+/// separate so that it can be rendered as its own widget.
+#[derive(Debug, Clone, PartialEq)]
+enum IconPiece {
+    /// Separators and the text around groups of icons
+    Markup(String),
+    Window {
+        id: u64,
+        markup: String,
+        /// CSS classes of the icon's label
+        classes: Vec<String>,
+    },
+}
+
+/// Appends markup to `pieces`, merging it with a preceding markup piece.
+fn push_markup(pieces: &mut Vec<IconPiece>, markup: &str) {
+    if markup.is_empty() {
+        return;
+    }
+    match pieces.last_mut() {
+        Some(IconPiece::Markup(prev)) => prev.push_str(markup),
+        _ => pieces.push(IconPiece::Markup(markup.to_string())),
+    }
+}
+
+/// Joins icon pieces back into a single markup string.
+fn icons_markup(pieces: &[IconPiece]) -> String {
+    pieces
+        .iter()
+        .map(|piece| match piece {
+            IconPiece::Markup(markup) | IconPiece::Window { markup, .. } => markup.as_str(),
+        })
+        .collect()
+}
+
+/// A run of window icons that is rendered as a unit by `format_window_icons`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IconGroup {
+    /// Tiled windows in the given column of the scrolling layout
+    Column(Option<usize>),
+    Floating,
+}
+
+impl IconGroup {
+    /// Whether a window in this group belongs with the previous window's group.
+    fn joins(&self, prev: &IconGroup) -> bool {
+        match (self, prev) {
+            (IconGroup::Column(Some(col)), IconGroup::Column(Some(prev_col))) => col == prev_col,
+            (IconGroup::Floating, IconGroup::Floating) => true,
+            _ => false,
+        }
+    }
+}
+
+fn format_output_header(format: &str, output: Option<&str>, info: Option<&OutputInfo>) -> String {
+    let make = info.map(|info| info.make.as_str()).unwrap_or_default();
+    let model = info.map(|info| info.model.as_str()).unwrap_or_default();
+    substitute(format, |placeholder| match placeholder {
+        "output" => Some(escape_markup(output.unwrap_or_default())),
+        "output-make" => Some(escape_markup(make)),
+        "output-model" => Some(escape_markup(model)),
+        _ => None,
+    })
+}
+
+/// Turns arbitrary text (output names, app_ids) into something usable as a
+/// CSS class name: lowercase, with anything but letters and digits replaced by
+/// '-'.
+fn css_class_name(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect()
 }
 
+/// The full label markup for a workspace, as the JSON output and previews show
+/// it. The bar renders the same pieces as separate widgets.
 fn format_workspace_label(cfg: &Config, info: &WorkspaceInfo) -> String {
-    let index = info.idx.to_string();
-    let name = &info.name;
-    let mut index_and_name = index.clone();
-    if !info.name.is_empty() {
-        index_and_name.push(' ');
-        index_and_name.push_str(name);
-    }
-    let value = if !info.name.is_empty() {
-        info.name.clone()
+    icons_markup(&workspace_label_pieces(cfg, info))
+}
+
+/// Formats `format` for a workspace, keeping each window icon a separate
+/// piece. The markup before and after {window-icons} has to be valid on its
+/// own, which `Config::from_user` makes sure of.
+fn workspace_label_pieces(cfg: &Config, info: &WorkspaceInfo) -> Vec<IconPiece> {
+    let separator = if info.icons.is_empty() {
+        ""
     } else {
-        index.clone()
+        &cfg.label_separator
+    };
+    let format_part = |part: &str| {
+        substitute(part, |placeholder| match placeholder {
+            "separator" => Some(separator.to_string()),
+            _ => name_placeholder(info, placeholder),
+        })
+    };
+
+    let mut pieces = Vec::new();
+    let Some((before, after)) = cfg.format.split_once("{window-icons}") else {
+        push_markup(&mut pieces, &format_part(&cfg.format));
+        return pieces;
+    };
+    push_markup(&mut pieces, &format_part(before));
+    // Icons that weren't split into pieces are rendered as a whole
+    if info.icon_pieces.is_empty() {
+        push_markup(&mut pieces, &info.icons);
+    }
+    for piece in &info.icon_pieces {
+        match piece {
+            IconPiece::Markup(markup) => push_markup(&mut pieces, markup),
+            IconPiece::Window { .. } => pieces.push(piece.clone()),
+        }
+    }
+    push_markup(&mut pieces, &format_part(after));
+    pieces
+}
+
+/// The value of a placeholder naming the workspace: {index}, {name},
+/// {index-and-name} or {value}.
+fn name_placeholder(info: &WorkspaceInfo, placeholder: &str) -> Option<String> {
+    let index = info.idx.to_string();
+    let value = match placeholder {
+        "index" => index,
+        "name" => info.name.clone(),
+        "index-and-name" if info.name.is_empty() => index,
+        "index-and-name" => format!("{} {}", index, info.name),
+        "value" if info.name.is_empty() => index,
+        "value" => info.name.clone(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Formats the name announced by screen readers for a workspace. Supports the
+/// name placeholders plus {windows} ("no windows", "1 window", ...) and
+/// {state} (e.g. "focused, urgent").
+fn format_accessible_name(format: &str, info: &WorkspaceInfo) -> String {
+    let windows = match info.window_count {
+        0 => "no windows".to_string(),
+        1 => "1 window".to_string(),
+        count => format!("{} windows", count),
     };
-    let separator = if info.icons.is_empty() { "" } else { ": " };
+    substitute(format, |placeholder| match placeholder {
+        "windows" => Some(windows.clone()),
+        "state" => Some(workspace_states(info).join(", ")),
+        _ => name_placeholder(info, placeholder),
+    })
+}
+
+/// State classes of a workspace and whether each one applies.
+fn workspace_classes(info: &WorkspaceInfo) -> [(&'static str, bool); 6] {
+    [
+        ("focused", info.is_focused),
+        ("urgent", info.is_urgent),
+        ("active", info.is_active),
+        ("empty", info.window_count == 0),
+        ("placeholder", info.is_placeholder),
+        ("previous", info.is_previous),
+    ]
+}
 
-    cfg.format
-        .replace("{index}", &index)
-        .replace("{name}", name)
-        .replace("{index-and-name}", &index_and_name)
-        .replace("{value}", &value)
-        .replace("{separator}", separator)
-        .replace("{window-icons}", &info.icons)
+/// Names of the states that apply to a workspace, e.g. `["focused", "active"]`.
+fn workspace_states(info: &WorkspaceInfo) -> Vec<&'static str> {
+    workspace_classes(info)
+        .into_iter()
+        .filter(|(_, applies)| *applies)
+        .map(|(state, _)| state)
+        .collect()
+}
+
+/// Moves keyboard focus between the workspace buttons in `container` with the
+/// arrow keys, Home and End.
+fn handle_navigation_key(container: &gtk::Box, key: gdk::keys::Key) -> Propagation {
+    let buttons = workspace_buttons(container);
+    let Some(current) = buttons.iter().position(|button| button.has_focus()) else {
+        return Propagation::Proceed;
+    };
+    match next_focus_index(current, buttons.len(), key) {
+        Some(next) => {
+            buttons[next].grab_focus();
+            Propagation::Stop
+        }
+        None => Propagation::Proceed,
+    }
+}
+
+/// Toggles the `urgent-pulse` class on urgent workspace buttons and window
+/// icons every `interval`, in step so that they blink together.
+fn pulse_urgent_workspaces(container: &gtk::Box, interval: Duration) {
+    let container = container.downgrade();
+    let mut on = false;
+    glib::timeout_add_local(interval, move || {
+        let Some(container) = container.upgrade() else {
+            return ControlFlow::Break;
+        };
+        on = !on;
+        for button in workspace_buttons(&container) {
+            set_urgent_pulse(button.upcast_ref(), on);
+        }
+        ControlFlow::Continue
+    });
+}
+
+/// Sets the `urgent-pulse` class on `widget` and its descendants that have the
+/// `urgent` class when `on`, and removes it otherwise.
+fn set_urgent_pulse(widget: &gtk::Widget, on: bool) {
+    let style = widget.style_context();
+    if on && style.has_class("urgent") {
+        style.add_class("urgent-pulse");
+    } else {
+        style.remove_class("urgent-pulse");
+    }
+    if let Some(container) = widget.downcast_ref::<gtk::Container>() {
+        for child in container.children() {
+            set_urgent_pulse(&child, on);
+        }
+    }
+}
+
+/// Workspace buttons in display order, including those in output sections.
+fn workspace_buttons(container: &gtk::Box) -> Vec<Button> {
+    let mut buttons = Vec::new();
+    for child in container.children() {
+        if let Some(button) = child.downcast_ref::<Button>() {
+            buttons.push(button.clone());
+        } else if let Some(section) = child.downcast_ref::<gtk::Box>() {
+            buttons.extend(
+                section
+                    .children()
+                    .into_iter()
+                    .filter_map(|child| child.downcast::<Button>().ok()),
+            );
+        }
+    }
+    buttons
+}
+
+/// Index of the button to focus after the buttons are rebuilt, when the button
+/// of workspace `id` at `index` had focus before: the same workspace's, or the
+/// one in its place if it's gone.
+fn refocus_index(ids: &[u64], id: u64, index: usize) -> Option<usize> {
+    ids.iter()
+        .position(|&other| other == id)
+        .or_else(|| Some(index.min(ids.len().checked_sub(1)?)))
+}
+
+/// Index of the button to focus after pressing `key` on button `current` of
+/// `len`, if the key moves focus.
+fn next_focus_index(current: usize, len: usize, key: gdk::keys::Key) -> Option<usize> {
+    use gdk::keys::constants as keys;
+    let next = if key == keys::Left || key == keys::Up {
+        current.checked_sub(1)?
+    } else if key == keys::Right || key == keys::Down {
+        current + 1
+    } else if key == keys::Home {
+        0
+    } else if key == keys::End {
+        len.checked_sub(1)?
+    } else {
+        return None;
+    };
+    (next < len && next != current).then_some(next)
 }
 
 struct NiriWorkspacesEnhanced;
@@ -85,15 +538,27 @@ impl Module for NiriWorkspacesEnhanced {
             log::warn!("Error initializing env_logger: {}", err);
         }
 
+        for problem in validate::check(&user_config, validate::pango_markup) {
+            log::error!("Invalid config: {}", problem);
+        }
+
         // Convert UserConfig to Config
         let config = Config::from_user(&user_config);
+        let overview_config = overview_config(&config);
 
         // Set up the box that we'll use to contain the actual window buttons.
         let root = info.get_root_widget();
         let container = gtk::Box::new(Orientation::Horizontal, 0);
         container.set_widget_name("workspaces");
+        container.connect_key_press_event(|container, event| {
+            handle_navigation_key(container, event.keyval())
+        });
         root.add(&container);
 
+        if let Some(interval) = config.urgent_pulse {
+            pulse_urgent_workspaces(&container, interval);
+        }
+
         // Create an async channel for sending workspace updates from the background thread
         let (tx, rx) = async_channel::unbounded();
 
@@ -101,7 +566,7 @@ impl Module for NiriWorkspacesEnhanced {
         std::thread::spawn({
             let config = config.clone();
             move || {
-                if let Err(err) = background_task(config, tx) {
+                if let Err(err) = background_task(config, tx, None) {
                     log::error!("Background task error: {}", err);
                 }
             }
@@ -110,61 +575,71 @@ impl Module for NiriWorkspacesEnhanced {
         // Spawn async task on the main context to receive updates
         let context = MainContext::default();
         context.spawn_local(async move {
-            while let Ok(mut ws_info) = rx.recv().await {
-                // Sort by workspace index (ascending order)
-                ws_info.sort_by_key(|info| info.idx);
+            // Workspace ids of the buttons, in display order
+            let mut button_ids: Vec<u64> = Vec::new();
+            while let Ok(update) = rx.recv().await {
+                let container_style = container.style_context();
+                if update.overview_open {
+                    container_style.add_class("overview");
+                } else {
+                    container_style.remove_class("overview");
+                }
+                let config = match &overview_config {
+                    Some(overview_config) if update.overview_open => overview_config,
+                    _ => &config,
+                };
+
+                // Looked up before hiding workspaces, which may hide it
+                let previous_workspace = update
+                    .workspaces
+                    .iter()
+                    .find(|info| info.is_previous)
+                    .map(|info| info.id);
+                let ws_info = displayed_workspaces(config, update.workspaces, &update.outputs);
+
+                // Rebuilding the buttons loses keyboard focus, so it's moved to
+                // the new button of the same workspace afterwards
+                let focused = workspace_buttons(&container)
+                    .iter()
+                    .position(|button| button.has_focus())
+                    .and_then(|index| Some((index, *button_ids.get(index)?)));
 
                 // Clear existing buttons
                 for child in container.children() {
                     container.remove(&child);
                 }
-
-                // Add new buttons in sorted order
-                for info in ws_info {
-                    let label = Label::new(None);
-                    label.set_markup(&format_workspace_label(&config, &info));
-
-                    let button = Button::new();
-                    button.add(&label);
-
-                    // Apply CSS classes based on workspace state
-                    let style_context = button.style_context();
-                    let classes = [
-                        ("focused", info.is_focused),
-                        ("urgent", info.is_urgent),
-                        ("active", info.is_active),
-                    ];
-                    for (class, should_add) in classes {
-                        if should_add {
-                            style_context.add_class(class);
-                        } else {
-                            style_context.remove_class(class);
+                button_ids.clear();
+
+                if config.group_by_output {
+                    let focused_output = ws_info
+                        .iter()
+                        .find(|info| info.is_focused)
+                        .and_then(|info| info.output.clone());
+                    for (output, ws_info) in group_by_output(ws_info) {
+                        let section = output_section(
+                            config,
+                            output.as_deref(),
+                            &update.outputs,
+                            output.is_some() && output == focused_output,
+                        );
+                        for info in ws_info {
+                            section.add(&workspace_button(config, &info, previous_workspace));
+                            button_ids.push(info.id);
                         }
+                        container.add(&section);
+                    }
+                } else {
+                    for info in ws_info {
+                        container.add(&workspace_button(config, &info, previous_workspace));
+                        button_ids.push(info.id);
                     }
-
-                    // Connect click handler to switch to workspace
-                    let workspace_id = info.id;
-                    button.connect_clicked(move |_| {
-                        // Spawn a blocking task to send the workspace switch command
-                        std::thread::spawn(move || {
-                            // TODO: use existing socket instead of making a new one here
-                            if let Ok(mut socket) = Socket::connect() {
-                                let request = Request::Action(niri_ipc::Action::FocusWorkspace {
-                                    reference: niri_ipc::WorkspaceReferenceArg::Id(workspace_id),
-                                });
-                                if let Err(err) = socket.send(request) {
-                                    log::error!("Failed to switch workspace: {}", err);
-                                }
-                            } else {
-                                log::error!("Failed to connect to niri socket");
-                            }
-                        });
-                    });
-
-                    container.add(&button);
                 }
 
                 container.show_all();
+                let refocus = focused.and_then(|(index, id)| refocus_index(&button_ids, id, index));
+                if let Some(index) = refocus {
+                    workspace_buttons(&container)[index].grab_focus();
+                }
             }
         });
 
@@ -174,57 +649,947 @@ impl Module for NiriWorkspacesEnhanced {
 
 waybar_module!(NiriWorkspacesEnhanced);
 
-#[derive(Debug, Clone)]
+/// The config used instead of `config` while niri's overview is open, if
+/// `overview-format` is set.
+fn overview_config(config: &Config) -> Option<Config> {
+    config.overview_format.clone().map(|format| Config {
+        format,
+        ..config.clone()
+    })
+}
+
+/// Sorts the workspaces of an update and drops the hidden ones.
+fn displayed_workspaces(
+    config: &Config,
+    mut ws_info: Vec<WorkspaceInfo>,
+    outputs: &[OutputInfo],
+) -> Vec<WorkspaceInfo> {
+    sort_workspaces(config.sort_by, &mut ws_info, outputs);
+    match &config.hide_empty {
+        Some(hide_empty) => hide_empty_workspaces(hide_empty, ws_info),
+        None => ws_info,
+    }
+}
+
+/// Creates the button for a single workspace. `previous_workspace` is the id of
+/// the workspace focused before the current one.
+fn workspace_button(
+    config: &Config,
+    info: &WorkspaceInfo,
+    previous_workspace: Option<u64>,
+) -> Button {
+    let button = Button::new();
+    let label = workspace_label(config, info);
+    match config.display {
+        Display::Icons => button.add(&label),
+        Display::Minimap => {
+            let content = gtk::Box::new(Orientation::Horizontal, 4);
+            content.add(&label);
+            if !info.columns.is_empty() {
+                content.add(&minimap::new_widget(
+                    info.columns.clone(),
+                    config.minimap_scale,
+                ));
+            }
+            button.add(&content);
+        }
+    }
+
+    // Screen readers would otherwise only see the icon glyphs
+    if let Some(accessible) = button.accessible() {
+        accessible.set_name(&format_accessible_name(&config.accessible_format, info));
+        accessible.set_description(&workspace_states(info).join(", "));
+    }
+    button.set_can_focus(true);
+
+    // Apply CSS classes based on workspace state
+    let style_context = button.style_context();
+    for (class, should_add) in workspace_classes(info) {
+        if should_add {
+            style_context.add_class(class);
+        } else {
+            style_context.remove_class(class);
+        }
+    }
+
+    connect_mouse_actions(&button, config, info, None, previous_workspace);
+
+    // Dragging a workspace onto another one moves it to that position, and
+    // dragging a window onto a workspace moves the window there. Placeholders
+    // have no position to take or give.
+    if !info.is_placeholder {
+        dnd::source(
+            &button,
+            dnd::Payload::Workspace {
+                id: info.id,
+                output: info.output.clone(),
+            },
+        );
+    }
+    let target = info.clone();
+    let focus = config.drag_window_focus;
+    dnd::dest(&button, true, move |payload| match payload {
+        dnd::Payload::Window(window_id) => {
+            send_actions(vec![niri_ipc::Action::MoveWindowToWorkspace {
+                window_id: Some(window_id),
+                reference: workspace_reference(&target),
+                focus,
+            }])
+        }
+        dnd::Payload::Workspace { id, output } => {
+            send_actions(workspace_drop_actions(id, output.as_deref(), &target))
+        }
+    });
+
+    button
+}
+
+/// The actions of window icons if `window_id` is set, else of workspaces.
+fn mouse_actions_for(config: &Config, window_id: Option<u64>) -> actions::MouseActions {
+    match window_id {
+        Some(_) => config.window_icon_actions.clone(),
+        None => config.workspace_actions.clone(),
+    }
+}
+
+/// Runs the configured mouse actions for events on `widget`, which is either a
+/// workspace button or the icon of `window_id`. Events without an action on a
+/// window icon fall through to its workspace button.
+fn connect_mouse_actions(
+    widget: &impl IsA<gtk::Widget>,
+    config: &Config,
+    info: &WorkspaceInfo,
+    window_id: Option<u64>,
+    previous_workspace: Option<u64>,
+) {
+    let mouse_actions = mouse_actions_for(config, window_id);
+    let persistent_workspaces = config.persistent_workspaces.clone();
+    let click_focused = config.click_focused;
+    let info = info.clone();
+    let scroll_actions = mouse_actions.clone();
+    let handle = move |event| {
+        let Some(action) = mouse_actions.get(event) else {
+            return false;
+        };
+        let resolved = actions::resolve(
+            action,
+            &info,
+            window_id,
+            &persistent_workspaces,
+            click_focused,
+            previous_workspace,
+        );
+        if let Some(resolved) = resolved {
+            actions::run(resolved);
+        }
+        true
+    };
+    let handle = std::rc::Rc::new(handle);
+
+    // Buttons are clicked with the primary button or from the keyboard, so
+    // plain clicks go through the "clicked" signal there
+    if let Some(button) = widget.dynamic_cast_ref::<Button>() {
+        let handle = handle.clone();
+        button.connect_clicked(move |_| {
+            handle(click_event(
+                gtk::current_event_state().unwrap_or(gdk::ModifierType::empty()),
+            ));
+        });
+    }
+
+    // Window icons can be dragged, so their clicks run on release, and only
+    // if no drag started since the press
+    let is_button = widget.is::<Button>();
+    let clicking = std::rc::Rc::new(std::cell::Cell::new(false));
+    if !is_button {
+        widget.add_events(gdk::EventMask::BUTTON_RELEASE_MASK);
+        widget.connect_drag_begin({
+            let clicking = clicking.clone();
+            move |_, _| clicking.set(false)
+        });
+        widget.connect_button_release_event({
+            let handle = handle.clone();
+            let clicking = clicking.clone();
+            move |_, event| {
+                if event.button() != 1 || !clicking.replace(false) {
+                    return Propagation::Proceed;
+                }
+                handle(click_event(event.state()));
+                Propagation::Stop
+            }
+        });
+    }
+
+    let click_actions = mouse_actions_for(config, window_id);
+    widget.connect_button_press_event({
+        let handle = handle.clone();
+        move |_, event| {
+            if event.event_type() != gdk::EventType::ButtonPress {
+                return Propagation::Proceed;
+            }
+            let mouse_event = match event.button() {
+                1 if is_button => return Propagation::Proceed,
+                1 => {
+                    // Keeps the workspace button from handling the click
+                    if click_actions.get(click_event(event.state())).is_none() {
+                        return Propagation::Proceed;
+                    }
+                    clicking.set(true);
+                    return Propagation::Stop;
+                }
+                2 => MouseEvent::MiddleClick,
+                3 => MouseEvent::RightClick,
+                _ => return Propagation::Proceed,
+            };
+            if handle(mouse_event) {
+                Propagation::Stop
+            } else {
+                Propagation::Proceed
+            }
+        }
+    });
+
+    widget.add_events(gdk::EventMask::SCROLL_MASK | gdk::EventMask::SMOOTH_SCROLL_MASK);
+    let scrolled = std::cell::Cell::new(0.0);
+    widget.connect_scroll_event(move |_, event| {
+        let mouse_event = match event.direction() {
+            gdk::ScrollDirection::Up => MouseEvent::ScrollUp,
+            gdk::ScrollDirection::Down => MouseEvent::ScrollDown,
+            gdk::ScrollDirection::Smooth => {
+                let delta = event.delta().1;
+                let direction = if delta < 0.0 {
+                    MouseEvent::ScrollUp
+                } else {
+                    MouseEvent::ScrollDown
+                };
+                if delta == 0.0 || scroll_actions.get(direction).is_none() {
+                    return Propagation::Proceed;
+                }
+                let mut total = scrolled.get();
+                let mouse_event = smooth_scroll_event(&mut total, delta);
+                scrolled.set(total);
+                match mouse_event {
+                    Some(mouse_event) => mouse_event,
+                    None => return Propagation::Stop,
+                }
+            }
+            _ => return Propagation::Proceed,
+        };
+        if handle(mouse_event) {
+            Propagation::Stop
+        } else {
+            Propagation::Proceed
+        }
+    });
+}
+
+/// Adds a smooth-scroll `delta` to `scrolled`, returning the event to fire
+/// each time it adds up to a full scroll step. Touchpads send many small
+/// deltas, which would otherwise flood niri with actions.
+fn smooth_scroll_event(scrolled: &mut f64, delta: f64) -> Option<MouseEvent> {
+    // Scrolling back starts over
+    if *scrolled * delta < 0.0 {
+        *scrolled = 0.0;
+    }
+    *scrolled += delta;
+    if scrolled.abs() < SMOOTH_SCROLL_STEP {
+        return None;
+    }
+    *scrolled -= SMOOTH_SCROLL_STEP.copysign(*scrolled);
+    Some(if delta < 0.0 {
+        MouseEvent::ScrollUp
+    } else {
+        MouseEvent::ScrollDown
+    })
+}
+
+fn click_event(state: gdk::ModifierType) -> MouseEvent {
+    if state.contains(gdk::ModifierType::SHIFT_MASK) {
+        MouseEvent::ShiftClick
+    } else if state.contains(gdk::ModifierType::CONTROL_MASK) {
+        MouseEvent::CtrlClick
+    } else {
+        MouseEvent::Click
+    }
+}
+
+/// Placeholders don't exist in niri yet, so they can only be referenced by
+/// name.
+fn workspace_reference(info: &WorkspaceInfo) -> niri_ipc::WorkspaceReferenceArg {
+    if info.is_placeholder {
+        niri_ipc::WorkspaceReferenceArg::Name(info.name.clone())
+    } else {
+        niri_ipc::WorkspaceReferenceArg::Id(info.id)
+    }
+}
+
+/// Creates the contents of a workspace button from `format`. Each window icon
+/// is its own widget so that it can be dragged to another workspace.
+fn workspace_label(config: &Config, info: &WorkspaceInfo) -> gtk::Box {
+    let content = gtk::Box::new(Orientation::Horizontal, 0);
+    for piece in workspace_label_pieces(config, info) {
+        match piece {
+            IconPiece::Markup(markup) => {
+                let label = Label::new(None);
+                label.set_markup(&markup);
+                content.add(&label);
+            }
+            IconPiece::Window {
+                id,
+                markup,
+                classes,
+            } => {
+                let label = Label::new(None);
+                label.set_markup(&markup);
+                let style_context = label.style_context();
+                for class in &classes {
+                    style_context.add_class(class);
+                }
+                // Labels have no window of their own to receive the drag
+                let event_box = gtk::EventBox::new();
+                event_box.add(&label);
+                dnd::source(&event_box, dnd::Payload::Window(id));
+                // Icons focus their window, never the previous workspace
+                connect_mouse_actions(&event_box, config, info, Some(id), None);
+                content.add(&event_box);
+            }
+        }
+    }
+    content
+}
+
+/// Creates the box holding the workspaces of one output, when grouping by
+/// output. `outputs` provides the make and model for the header.
+fn output_section(
+    config: &Config,
+    output: Option<&str>,
+    outputs: &[OutputInfo],
+    is_current: bool,
+) -> gtk::Box {
+    let section = gtk::Box::new(Orientation::Horizontal, 0);
+    let style_context = section.style_context();
+    style_context.add_class("output");
+    if let Some(output) = output {
+        style_context.add_class(&format!("output-{}", css_class_name(output)));
+    }
+    if is_current {
+        style_context.add_class("current-output");
+    }
+
+    if let Some(format) = &config.output_header_format {
+        let info = outputs
+            .iter()
+            .find(|info| Some(info.name.as_str()) == output);
+        let header = Label::new(None);
+        header.set_markup(&format_output_header(format, output, info));
+        header.style_context().add_class("output-header");
+        section.add(&header);
+    }
+
+    // Dropping a workspace anywhere else in the section moves it to the output
+    if let Some(output) = output {
+        let output = output.to_string();
+        dnd::dest(&section, false, move |payload| {
+            if let dnd::Payload::Workspace { id, output: from } = payload
+                && from.as_ref() != Some(&output)
+            {
+                send_actions(vec![niri_ipc::Action::MoveWorkspaceToMonitor {
+                    output: output.clone(),
+                    reference: Some(niri_ipc::WorkspaceReferenceArg::Id(id)),
+                }]);
+            }
+        });
+    }
+
+    section
+}
+
+/// Actions that move the workspace `id`, currently on `output`, to the
+/// position of `target`.
+fn workspace_drop_actions(
+    id: u64,
+    output: Option<&str>,
+    target: &WorkspaceInfo,
+) -> Vec<niri_ipc::Action> {
+    if id == target.id || target.is_placeholder {
+        return Vec::new();
+    }
+
+    let reference = Some(niri_ipc::WorkspaceReferenceArg::Id(id));
+    let mut actions = Vec::new();
+    if let Some(target_output) = &target.output
+        && output != Some(target_output.as_str())
+    {
+        actions.push(niri_ipc::Action::MoveWorkspaceToMonitor {
+            output: target_output.clone(),
+            reference: reference.clone(),
+        });
+    }
+    actions.push(niri_ipc::Action::MoveWorkspaceToIndex {
+        index: usize::from(target.idx),
+        reference,
+    });
+    actions
+}
+
+/// Sends an action to niri from a new thread, so the UI doesn't block on the
+/// socket.
+fn send_action(action: niri_ipc::Action) {
+    send_actions(vec![action]);
+}
+
+/// Sends actions to niri in order from a new thread, so the UI doesn't block on
+/// the socket. Stops at the first action that fails.
+fn send_actions(actions: Vec<niri_ipc::Action>) {
+    if actions.is_empty() {
+        return;
+    }
+    std::thread::spawn(move || {
+        // TODO: use existing socket instead of making a new one here
+        let Ok(mut socket) = Socket::connect() else {
+            log::error!("Failed to connect to niri socket");
+            return;
+        };
+        send_actions_on(&mut socket, actions);
+    });
+}
+
+/// Sends actions to niri in order over `socket`, blocking until each one is
+/// answered. Stops at the first action that fails.
+fn send_actions_on(socket: &mut Socket, actions: Vec<niri_ipc::Action>) {
+    for action in actions {
+        match socket.send(Request::Action(action)) {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => {
+                log::error!("niri rejected action: {}", err);
+                return;
+            }
+            Err(err) => {
+                log::error!("Failed to send action: {}", err);
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 struct WorkspaceInfo {
     id: u64,
     name: String,
+    /// Markup for all window icons, joined from `icon_pieces`
     icons: String,
+    icon_pieces: Vec<IconPiece>,
     idx: u8,
     is_focused: bool,
     is_urgent: bool,
     is_active: bool,
+    /// Columns of the scrolling layout, for the minimap display mode
+    columns: Vec<minimap::MinimapColumn>,
+    output: Option<String>,
+    window_count: usize,
+    active_window_id: Option<u64>,
+    /// The workspace that was focused before the current one
+    is_previous: bool,
+    /// A `persistent-workspaces` entry that doesn't exist in niri yet. Its `id`
+    /// is synthetic, counting down from `u64::MAX` so it can't clash with
+    /// niri's.
+    is_placeholder: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct OutputInfo {
+    name: String,
+    make: String,
+    model: String,
+    /// Position of the output in the global coordinate space, if it is enabled
+    position: Option<(i32, i32)>,
+}
+
+/// Everything the UI needs to render, sent by the background thread on each
+/// update.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Update {
+    workspaces: Vec<WorkspaceInfo>,
+    outputs: Vec<OutputInfo>,
+    overview_open: bool,
+}
+
+/// Sorts workspaces for display. Every ordering falls back to the output, the
+/// index and the id, so the result doesn't depend on the input order.
+fn sort_workspaces(sort_by: SortBy, ws_info: &mut [WorkspaceInfo], outputs: &[OutputInfo]) {
+    // Outputs ordered left to right, then top to bottom. Disabled or unknown
+    // outputs go last.
+    let mut by_position: Vec<&OutputInfo> = outputs.iter().collect();
+    by_position.sort_by_key(|output| {
+        let (x, y) = output.position.unwrap_or((i32::MAX, i32::MAX));
+        (x, y, &output.name)
+    });
+    let output_rank = |info: &WorkspaceInfo| {
+        by_position
+            .iter()
+            .position(|output| Some(&output.name) == info.output.as_ref())
+            .unwrap_or(usize::MAX)
+    };
+
+    // Workspaces without an output go last
+    let fallback = |info: &WorkspaceInfo| {
+        (
+            info.output.is_none(),
+            info.output.clone(),
+            info.idx,
+            info.id,
+        )
+    };
+    match sort_by {
+        SortBy::OutputThenIdx => ws_info.sort_by_key(fallback),
+        SortBy::Idx => ws_info.sort_by_key(|info| (info.idx, fallback(info))),
+        SortBy::Name => ws_info.sort_by_key(|info| {
+            // Unnamed workspaces go last
+            (
+                info.name.is_empty(),
+                info.name.to_lowercase(),
+                fallback(info),
+            )
+        }),
+        // Placeholders don't have a real id, so they go last
+        SortBy::Id => ws_info.sort_by_key(|info| (info.is_placeholder, info.id, fallback(info))),
+        SortBy::OutputPosition => ws_info.sort_by_key(|info| (output_rank(info), fallback(info))),
+    }
+}
+
+/// Splits sorted workspaces into one group per output, in the order each
+/// output first appears.
+fn group_by_output(ws_info: Vec<WorkspaceInfo>) -> Vec<(Option<String>, Vec<WorkspaceInfo>)> {
+    let mut groups: Vec<(Option<String>, Vec<WorkspaceInfo>)> = Vec::new();
+    for info in ws_info {
+        match groups.iter_mut().find(|(output, _)| *output == info.output) {
+            Some((_, group)) => group.push(info),
+            None => groups.push((info.output.clone(), vec![info])),
+        }
+    }
+    groups
+}
+
+/// Adds a placeholder for each persistent workspace that doesn't exist in
+/// niri. Entries without an output get their placeholder on the focused output,
+/// or the first one if none is focused. Placeholders are ordered after the real
+/// workspaces of their output.
+fn add_persistent_workspaces(persistent: &[PersistentWorkspace], ws_info: &mut Vec<WorkspaceInfo>) {
+    let default_output = ws_info
+        .iter()
+        .find(|info| info.is_focused)
+        .or_else(|| ws_info.first())
+        .and_then(|info| info.output.clone());
+
+    // niri assigns ids counting up from 1, so placeholders count down from the
+    // top, one per entry whether or not it exists. That keeps the id of a
+    // placeholder the same between updates.
+    for (i, entry) in persistent.iter().enumerate() {
+        // niri's workspace names are unique across outputs and matched
+        // case-insensitively
+        let exists = ws_info
+            .iter()
+            .any(|info| info.name.to_lowercase() == entry.name.to_lowercase());
+        if exists {
+            continue;
+        }
+
+        let output = entry.output.clone().or_else(|| default_output.clone());
+        let last_idx = ws_info
+            .iter()
+            .filter(|info| info.output == output)
+            .map(|info| info.idx)
+            .max()
+            .unwrap_or(0);
+        ws_info.push(WorkspaceInfo {
+            id: u64::MAX - i as u64,
+            name: entry.name.clone(),
+            idx: last_idx.saturating_add(1),
+            output,
+            is_placeholder: true,
+            ..Default::default()
+        });
+    }
+}
+
+/// Removes workspaces without windows, except for the ones `hide_empty` says to
+/// keep.
+fn hide_empty_workspaces(
+    hide_empty: &HideEmpty,
+    ws_info: Vec<WorkspaceInfo>,
+) -> Vec<WorkspaceInfo> {
+    // niri keeps an empty workspace at the end of each output
+    let mut trailing: HashMap<Option<&str>, (u8, u64)> = HashMap::new();
+    for info in ws_info.iter().filter(|info| !info.is_placeholder) {
+        let last = trailing
+            .entry(info.output.as_deref())
+            .or_insert((info.idx, info.id));
+        if info.idx > last.0 {
+            *last = (info.idx, info.id);
+        }
+    }
+    let trailing_ids: Vec<u64> = trailing.into_values().map(|(_, id)| id).collect();
+
+    ws_info
+        .into_iter()
+        .filter(|info| {
+            info.window_count > 0
+                || (hide_empty.keep_focused && info.is_focused)
+                || (hide_empty.keep_named && !info.name.is_empty())
+                || (hide_empty.keep_trailing && trailing_ids.contains(&info.id))
+                || info.is_placeholder
+        })
+        .collect()
+}
+
+/// State tracked from niri's event stream between updates.
+#[derive(Debug, Default)]
+struct EventState {
+    /// niri sends the current overview state when subscribing to events
+    overview_open: bool,
+    focus_history: FocusHistory,
+    /// The last update sent to the UI, to skip sending identical ones
+    last_sent: Option<Update>,
+    urgency: UrgencyTimes,
+}
+
+/// When workspaces and windows became urgent, for `urgent-timeout`.
+#[derive(Debug, Default)]
+struct UrgencyTimes {
+    workspaces: HashMap<u64, Instant>,
+    windows: HashMap<u64, Instant>,
+}
+
+impl UrgencyTimes {
+    /// Records when workspaces and windows became urgent, and clears
+    /// `is_urgent` on the ones that have been urgent for `timeout` or longer.
+    fn expire(
+        &mut self,
+        workspaces: &mut [niri_ipc::Workspace],
+        windows: &mut [Window],
+        timeout: Option<Duration>,
+        now: Instant,
+    ) {
+        let workspaces = workspaces.iter_mut().map(|ws| (ws.id, &mut ws.is_urgent));
+        let windows = windows
+            .iter_mut()
+            .map(|window| (window.id, &mut window.is_urgent));
+        expire_urgency(&mut self.workspaces, workspaces, timeout, now);
+        expire_urgency(&mut self.windows, windows, timeout, now);
+    }
+
+    /// Time until the next workspace or window stops being shown as urgent.
+    fn next_expiry(&self, timeout: Duration, now: Instant) -> Option<Duration> {
+        self.workspaces
+            .values()
+            .chain(self.windows.values())
+            .map(|&since| since + timeout)
+            .filter(|&expiry| expiry > now)
+            .min()
+            .map(|expiry| expiry - now)
+    }
+}
+
+fn expire_urgency<'a>(
+    since: &mut HashMap<u64, Instant>,
+    items: impl Iterator<Item = (u64, &'a mut bool)>,
+    timeout: Option<Duration>,
+    now: Instant,
+) {
+    let mut urgent = HashMap::new();
+    for (id, is_urgent) in items {
+        if !*is_urgent {
+            continue;
+        }
+        let start = since.get(&id).copied().unwrap_or(now);
+        urgent.insert(id, start);
+        if timeout.is_some_and(|timeout| now >= start + timeout) {
+            *is_urgent = false;
+        }
+    }
+    // Forget items that are no longer urgent, so they expire anew next time
+    *since = urgent;
 }
 
+/// Limits updates to one per `interval`. The first update after a quiet
+/// period is sent right away; later ones are coalesced into a single update at
+/// the end of the interval.
+#[derive(Debug)]
+struct Debouncer {
+    interval: Duration,
+    last_update: Option<Instant>,
+    pending: bool,
+}
+
+impl Debouncer {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_update: None,
+            pending: false,
+        }
+    }
+
+    /// Called when something changed. Returns whether to update right away;
+    /// otherwise an update is due after `timeout`.
+    fn changed(&mut self, now: Instant) -> bool {
+        match self.last_update {
+            Some(last) if now < last + self.interval => {
+                self.pending = true;
+                false
+            }
+            _ => {
+                self.updated(now);
+                true
+            }
+        }
+    }
+
+    /// Time left until a coalesced update is due, if there is one.
+    fn timeout(&self, now: Instant) -> Option<Duration> {
+        let last = self.last_update?;
+        self.pending
+            .then(|| (last + self.interval).saturating_duration_since(now))
+    }
+
+    fn updated(&mut self, now: Instant) {
+        self.last_update = Some(now);
+        self.pending = false;
+    }
+}
+
+/// The focused workspace and the one focused before it.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FocusHistory {
+    current: Option<u64>,
+    previous: Option<u64>,
+}
+
+impl FocusHistory {
+    fn focus(&mut self, id: u64) {
+        if self.current != Some(id) {
+            self.previous = self.current.replace(id);
+        }
+    }
+}
+
+/// Input for the background task.
+enum Message {
+    Event(Event),
+    EventStreamClosed,
+    /// `window-icons-file` was changed and parsed successfully
+    WindowIcons(HashMap<String, String>),
+}
+
+/// Sends an update whenever niri's state changes, until the event stream
+/// ends. `socket` overrides the path in `NIRI_SOCKET`.
 fn background_task(
-    config: Config,
-    tx: async_channel::Sender<Vec<WorkspaceInfo>>,
+    mut config: Config,
+    tx: async_channel::Sender<Update>,
+    socket: Option<&std::path::Path>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut cmd_socket = Socket::connect()?;
-    let mut subscribe_socket = Socket::connect()?;
+    let connect = || match socket {
+        Some(path) => Socket::connect_to(path),
+        None => Socket::connect(),
+    };
+    let mut cmd_socket = connect()?;
+    let mut subscribe_socket = connect()?;
+
+    let recorder =
+        match &config.record_events {
+            Some(path) => Some(recording::Recorder::open(path).map_err(|err| {
+                format!("Failed to open {} for recording: {}", path.display(), err)
+            })?),
+            None => None,
+        };
+    let mut request = |request: Request| -> Result<Response, Box<dyn std::error::Error>> {
+        let response = cmd_socket.send(request.clone())??;
+        if let Some(recorder) = &recorder {
+            recorder.reply(&request, &response);
+        }
+        Ok(response)
+    };
+
+    if let Some(file_icons) = config.load_window_icons_file()
+        && let Some(recorder) = &recorder
+    {
+        recorder.window_icons(&file_icons);
+    }
+
+    let mut state = EventState::default();
 
     // Initial update
-    update_workspaces(&config, &tx, &mut cmd_socket)?;
+    update_workspaces(&config, &tx, &mut request, &mut state)?;
 
     let Ok(Response::Handled) = subscribe_socket.send(Request::EventStream)? else {
         return Err("Expected Handled response".into());
     };
 
-    let mut read_event = subscribe_socket.read_events();
-    while let Ok(event) = read_event() {
-        if matches!(
-            event,
-            Event::WindowOpenedOrChanged { .. }
-                | Event::WindowClosed { .. }
-                | Event::WindowLayoutsChanged { .. }
-                | Event::WindowFocusChanged { .. }
-                | Event::WorkspacesChanged { .. }
-        ) {
-            update_workspaces(&config, &tx, &mut cmd_socket)?;
+    let (messages_tx, messages) = std::sync::mpsc::channel();
+    std::thread::spawn({
+        let messages_tx = messages_tx.clone();
+        move || {
+            let mut read_event = subscribe_socket.read_events();
+            while let Ok(event) = read_event() {
+                if messages_tx.send(Message::Event(event)).is_err() {
+                    return;
+                }
+            }
+            let _ = messages_tx.send(Message::EventStreamClosed);
+        }
+    });
+    if let Some(path) = config.window_icons_file.clone() {
+        std::thread::spawn(move || watch_window_icons_file(&path, messages_tx));
+    }
+
+    let mut debouncer = Debouncer::new(config.update_debounce);
+    debouncer.updated(Instant::now());
+    loop {
+        let now = Instant::now();
+        let urgency_expiry = config
+            .urgent_timeout
+            .and_then(|timeout| state.urgency.next_expiry(timeout, now));
+        let timeout = debouncer
+            .timeout(now)
+            .into_iter()
+            .chain(urgency_expiry)
+            .min();
+        let message = match timeout {
+            Some(timeout) => match messages.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    update_workspaces(&config, &tx, &mut request, &mut state)?;
+                    debouncer.updated(Instant::now());
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match messages.recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            },
+        };
+        match message {
+            Message::Event(event) => {
+                if let Some(recorder) = &recorder {
+                    recorder.event(&event);
+                }
+                if !apply_event(&mut state, &event) {
+                    continue;
+                }
+            }
+            Message::EventStreamClosed => break,
+            Message::WindowIcons(file_icons) => {
+                if let Some(recorder) = &recorder {
+                    recorder.window_icons(&file_icons);
+                }
+                config.window_icons = merge_window_icons(&file_icons, &config.user_window_icons);
+            }
+        }
+        if debouncer.changed(Instant::now()) {
+            update_workspaces(&config, &tx, &mut request, &mut state)?;
         }
     }
 
     Ok(())
 }
 
+/// Tracks `event` in `state`, returning whether the workspaces need to be
+/// updated.
+fn apply_event(state: &mut EventState, event: &Event) -> bool {
+    match event {
+        Event::OverviewOpenedOrClosed { is_open } => {
+            state.overview_open = *is_open;
+            true
+        }
+        Event::WorkspaceActivated { id, focused } => {
+            if *focused {
+                state.focus_history.focus(*id);
+            }
+            true
+        }
+        Event::WindowOpenedOrChanged { .. }
+        | Event::WindowClosed { .. }
+        | Event::WindowLayoutsChanged { .. }
+        | Event::WindowFocusChanged { .. }
+        | Event::WindowUrgencyChanged { .. }
+        | Event::WorkspaceUrgencyChanged { .. }
+        | Event::WorkspacesChanged { .. } => true,
+        _ => false,
+    }
+}
+
+/// Sends the contents of `path` whenever it changes. Files that fail to parse
+/// are logged and skipped, so the previous icons stay in use.
+fn watch_window_icons_file(path: &std::path::Path, messages: std::sync::mpsc::Sender<Message>) {
+    let result = icons_file::watch(path, || match icons_file::load(path) {
+        Ok(icons) => {
+            log::info!("Reloaded {}", path.display());
+            let _ = messages.send(Message::WindowIcons(icons));
+        }
+        Err(err) => log::error!(
+            "Failed to reload {}, keeping the previous icons: {}",
+            path.display(),
+            err
+        ),
+    });
+    if let Err(err) = result {
+        log::error!("Failed to watch {}: {}", path.display(), err);
+    }
+}
+
+/// Fetches niri's state with `request` and sends the resulting update.
 fn update_workspaces(
     config: &Config,
-    tx: &async_channel::Sender<Vec<WorkspaceInfo>>,
-    cmd_socket: &mut Socket,
+    tx: &async_channel::Sender<Update>,
+    request: &mut impl FnMut(Request) -> Result<Response, Box<dyn std::error::Error>>,
+    state: &mut EventState,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let Response::Workspaces(workspaces) = cmd_socket.send(Request::Workspaces)?? else {
+    let Response::Workspaces(mut workspaces) = request(Request::Workspaces)? else {
         return Err("Expected Workspaces response".into());
     };
+    let Response::Windows(mut windows) = request(Request::Windows)? else {
+        return Err("Expected Windows response".into());
+    };
+    let Response::Outputs(outputs) = request(Request::Outputs)? else {
+        return Err("Expected Outputs response".into());
+    };
+
+    // Focus can also change without a WorkspaceActivated event, e.g. when the
+    // focused workspace is removed
+    if let Some(focused) = workspaces.iter().find(|ws| ws.is_focused) {
+        state.focus_history.focus(focused.id);
+    }
+
+    state.urgency.expire(
+        &mut workspaces,
+        &mut windows,
+        config.urgent_timeout,
+        Instant::now(),
+    );
+
+    let update = build_update(config, &workspaces, windows, outputs, state);
+    // Layout changes often don't change anything shown, e.g. resizing a column
+    if state.last_sent.as_ref() == Some(&update) {
+        return Ok(());
+    }
+    state.last_sent = Some(update.clone());
+    // Send to main thread (using blocking send since we're in a blocking thread)
+    tx.send_blocking(update)
+        .map_err(|_| "Failed to send workspace info")?;
+
+    Ok(())
+}
 
+/// Builds an update from niri's replies to the Workspaces, Windows and Outputs
+/// requests.
+fn build_update(
+    config: &Config,
+    workspaces: &[niri_ipc::Workspace],
+    mut windows: Vec<Window>,
+    outputs: HashMap<String, niri_ipc::Output>,
+    state: &EventState,
+) -> Update {
     // Store workspace info using WorkspaceInfo struct
     let mut ws_info: HashMap<u64, WorkspaceInfo> = workspaces
         .iter()
@@ -235,153 +1600,581 @@ fn update_workspaces(
                     id: ws.id,
                     name: ws.name.clone().unwrap_or_default(),
                     icons: String::new(),
+                    icon_pieces: Vec::new(),
                     idx: ws.idx,
                     is_focused: ws.is_focused,
                     is_urgent: ws.is_urgent,
                     is_active: ws.is_active,
+                    columns: Vec::new(),
+                    output: ws.output.clone(),
+                    window_count: 0,
+                    active_window_id: ws.active_window_id,
+                    is_previous: state.focus_history.previous == Some(ws.id),
+                    is_placeholder: false,
                 },
             )
         })
         .collect();
 
-    let Response::Windows(mut windows) = cmd_socket.send(Request::Windows)?? else {
-        return Err("Expected Windows response".into());
-    };
-
-    // Sort windows by their position in the scrolling layout
-    windows.sort_by_key(|w| w.layout.pos_in_scrolling_layout);
-
-    // Collect icons and track if workspace has urgent windows
-    for (workspace_id, window) in windows
-        .iter()
-        .filter_map(|w| w.workspace_id.map(|id| (id, w)))
-    {
-        let raw_icon = get_raw_icon(config, window);
-        let formatted_icon = format_icon(config, &raw_icon, window.is_focused, window.is_urgent);
+    // Sort windows by their position in the scrolling layout. Floating windows
+    // have no position, so fall back to the window id to keep them in a stable
+    // order.
+    windows.sort_by_key(|w| (w.layout.pos_in_scrolling_layout, w.id));
+
+    // Group windows by workspace, keeping the scrolling layout order
+    let mut windows_by_workspace: HashMap<u64, Vec<&Window>> = HashMap::new();
+    for window in &windows {
+        if let Some(workspace_id) = window.workspace_id {
+            windows_by_workspace
+                .entry(workspace_id)
+                .or_default()
+                .push(window);
+        }
+    }
 
-        if let Some(ws) = ws_info.get_mut(&workspace_id) {
-            if !ws.icons.is_empty() {
-                ws.icons.push(' ');
-            }
-            ws.icons.push_str(&formatted_icon);
+    for ws in workspaces {
+        let (Some(info), Some(ws_windows)) =
+            (ws_info.get_mut(&ws.id), windows_by_workspace.get(&ws.id))
+        else {
+            continue;
+        };
+        info.icon_pieces = format_window_icons(config, ws_windows, ws.active_window_id);
+        info.icons = icons_markup(&info.icon_pieces);
+        info.window_count = ws_windows.len();
+        if config.display == Display::Minimap {
+            info.columns = minimap::columns_from_windows(ws_windows);
         }
     }
 
     // Convert to Vec for sending
-    let ws_vec: Vec<WorkspaceInfo> = ws_info.into_values().collect();
-
-    // Send to main thread (using blocking send since we're in a blocking thread)
-    tx.send_blocking(ws_vec)
-        .map_err(|_| "Failed to send workspace info")?;
+    let mut ws_vec: Vec<WorkspaceInfo> = ws_info.into_values().collect();
+    // In a stable order, so that unchanged updates compare equal
+    ws_vec.sort_by_key(|info| info.id);
+    add_persistent_workspaces(&config.persistent_workspaces, &mut ws_vec);
+
+    let mut outputs: Vec<OutputInfo> = outputs
+        .into_values()
+        .map(|output| OutputInfo {
+            position: output.logical.map(|logical| (logical.x, logical.y)),
+            name: output.name,
+            make: output.make,
+            model: output.model,
+        })
+        .collect();
+    outputs.sort_by(|a, b| a.name.cmp(&b.name));
 
-    Ok(())
+    Update {
+        workspaces: ws_vec,
+        outputs,
+        overview_open: state.overview_open,
+    }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 struct UserWindowIconFormats {
     focused: Option<String>,
     urgent: Option<String>,
+    #[serde(default)]
+    active: Option<String>,
+    #[serde(default)]
+    floating: Option<String>,
     default: Option<String>,
+    /// Keys that don't match any field, reported by `validate`
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_json::Value>,
+}
+
+impl UserWindowIconFormats {
+    /// Each format along with the name of its state.
+    fn iter(&self) -> [(&'static str, &Option<String>); 5] {
+        [
+            ("focused", &self.focused),
+            ("urgent", &self.urgent),
+            ("active", &self.active),
+            ("floating", &self.floating),
+            ("default", &self.default),
+        ]
+    }
+}
+
+/// Where floating windows are placed relative to tiled windows.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum FloatingPosition {
+    #[default]
+    Before,
+    After,
 }
-#[derive(Deserialize, Debug, Clone)]
+
+/// How the contents of each workspace button are rendered.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Display {
+    /// A label built from `format`
+    #[default]
+    Icons,
+    /// The label followed by a drawing of the workspace's columns
+    Minimap,
+}
+
+/// Order in which workspaces are displayed.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum SortBy {
+    /// Grouped by output name, then by index on the output
+    #[default]
+    OutputThenIdx,
+    /// By index on the output, regardless of the output
+    Idx,
+    /// By name, with unnamed workspaces last
+    Name,
+    /// By niri's workspace id, roughly the order they were created in
+    Id,
+    /// Grouped by output, ordered by the outputs' positions left to right
+    OutputPosition,
+}
+
+/// What the default click action does on the already focused workspace.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum ClickFocused {
+    /// Focus it again, which does nothing
+    #[default]
+    Focus,
+    /// Open or close niri's overview
+    ToggleOverview,
+    /// Switch back to the previously focused workspace
+    Previous,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+enum UserHideEmpty {
+    Enabled(bool),
+    Options(UserHideEmptyOptions),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct UserHideEmptyOptions {
+    #[serde(default, rename = "keep-focused")]
+    keep_focused: Option<bool>,
+    #[serde(default, rename = "keep-named")]
+    keep_named: Option<bool>,
+    #[serde(default, rename = "keep-trailing")]
+    keep_trailing: Option<bool>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_json::Value>,
+}
+
+/// Which empty workspaces remain visible when `hide-empty` is enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HideEmpty {
+    keep_focused: bool,
+    keep_named: bool,
+    /// Keep the empty workspace niri keeps at the end of each output
+    keep_trailing: bool,
+}
+
+impl Default for HideEmpty {
+    fn default() -> Self {
+        Self {
+            keep_focused: true,
+            keep_named: false,
+            keep_trailing: false,
+        }
+    }
+}
+
+impl HideEmpty {
+    fn from_user(user: &UserHideEmpty) -> Option<Self> {
+        let defaults = Self::default();
+        match user {
+            UserHideEmpty::Enabled(false) => None,
+            UserHideEmpty::Enabled(true) => Some(defaults),
+            UserHideEmpty::Options(options) => Some(Self {
+                keep_focused: options.keep_focused.unwrap_or(defaults.keep_focused),
+                keep_named: options.keep_named.unwrap_or(defaults.keep_named),
+                keep_trailing: options.keep_trailing.unwrap_or(defaults.keep_trailing),
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+enum UserPersistentWorkspaces {
+    All(Vec<UserPersistentWorkspace>),
+    /// Entries keyed by output name
+    PerOutput(HashMap<String, Vec<UserPersistentWorkspace>>),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+enum UserPersistentWorkspace {
+    Name(String),
+    Full {
+        name: String,
+        #[serde(default)]
+        spawn: Option<Vec<String>>,
+        /// Keys that don't match any field, reported by `validate`
+        #[serde(flatten)]
+        unknown: BTreeMap<String, serde_json::Value>,
+    },
+}
+
+/// A workspace that is always shown, even before niri creates it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PersistentWorkspace {
+    name: String,
+    /// Output to show the workspace on, or None for all outputs
+    output: Option<String>,
+    /// Command niri spawns when the placeholder is clicked
+    spawn: Option<Vec<String>>,
+}
+
+impl PersistentWorkspace {
+    fn from_user(user: &UserPersistentWorkspaces) -> Vec<Self> {
+        let entries: Vec<(Option<&String>, &UserPersistentWorkspace)> = match user {
+            UserPersistentWorkspaces::All(entries) => entries.iter().map(|e| (None, e)).collect(),
+            UserPersistentWorkspaces::PerOutput(outputs) => {
+                // Sort by output so the order doesn't depend on the HashMap
+                let mut outputs: Vec<_> = outputs.iter().collect();
+                outputs.sort_by_key(|(output, _)| *output);
+                outputs
+                    .into_iter()
+                    .flat_map(|(output, entries)| entries.iter().map(move |e| (Some(output), e)))
+                    .collect()
+            }
+        };
+
+        entries
+            .into_iter()
+            .map(|(output, entry)| {
+                let (name, spawn) = match entry {
+                    UserPersistentWorkspace::Name(name) => (name.clone(), None),
+                    UserPersistentWorkspace::Full { name, spawn, .. } => {
+                        (name.clone(), spawn.clone())
+                    }
+                };
+                Self {
+                    name,
+                    output: output.cloned(),
+                    spawn,
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 struct UserConfig {
     #[serde(default)]
     format: Option<String>,
     #[serde(default, rename = "window-icons")]
     window_icons: Option<HashMap<String, String>>,
+    #[serde(default, rename = "window-icons-file")]
+    window_icons_file: Option<String>,
     #[serde(default, rename = "window-icon-default")]
     window_icon_default: Option<String>,
     #[serde(default, rename = "window-icon-format")]
     window_icon_formats: Option<UserWindowIconFormats>,
+    #[serde(default, rename = "icon-separator")]
+    icon_separator: Option<String>,
+    #[serde(default, rename = "column-separator")]
+    column_separator: Option<String>,
+    #[serde(default, rename = "label-separator")]
+    label_separator: Option<String>,
+    #[serde(default, rename = "column-format")]
+    column_format: Option<String>,
+    #[serde(default, rename = "column-format-single")]
+    column_format_single: Option<String>,
+    #[serde(default, rename = "floating-format")]
+    floating_format: Option<String>,
+    #[serde(default, rename = "floating-position")]
+    floating_position: Option<FloatingPosition>,
+    #[serde(default)]
+    display: Option<Display>,
+    #[serde(default, rename = "minimap-scale")]
+    minimap_scale: Option<f64>,
+    #[serde(default, rename = "hide-empty")]
+    hide_empty: Option<UserHideEmpty>,
+    #[serde(default, rename = "persistent-workspaces")]
+    persistent_workspaces: Option<UserPersistentWorkspaces>,
+    #[serde(default, rename = "sort-by")]
+    sort_by: Option<SortBy>,
+    #[serde(default, rename = "group-by-output")]
+    group_by_output: Option<bool>,
+    #[serde(default, rename = "output-header-format")]
+    output_header_format: Option<String>,
+    #[serde(default, rename = "drag-window-focus")]
+    drag_window_focus: Option<bool>,
+    #[serde(default, rename = "accessible-format")]
+    accessible_format: Option<String>,
+    #[serde(default, rename = "overview-format")]
+    overview_format: Option<String>,
+    #[serde(default, rename = "click-focused")]
+    click_focused: Option<ClickFocused>,
+    #[serde(default, rename = "workspace-actions")]
+    workspace_actions: Option<actions::UserMouseActions>,
+    #[serde(default, rename = "window-icon-actions")]
+    window_icon_actions: Option<actions::UserMouseActions>,
+    #[serde(default, rename = "record-events")]
+    record_events: Option<String>,
+    #[serde(default, rename = "update-debounce")]
+    update_debounce: Option<u64>,
+    #[serde(default, rename = "urgent-timeout")]
+    urgent_timeout: Option<f64>,
+    #[serde(default, rename = "urgent-pulse")]
+    urgent_pulse: Option<u64>,
+    /// Keys that don't match any field. Besides typos, these include keys
+    /// handled by waybar itself, like `module_path`.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone)]
 struct WindowIconFormats {
     focused: String,
     urgent: String,
+    active: String,
+    floating: String,
     default: String,
 }
 
 impl WindowIconFormats {
+    /// Formats without "{icon}" are replaced with the default for their state,
+    /// as reported by `validate`.
     fn from_user(user_formats: &UserWindowIconFormats) -> Self {
-        // Validate and warn on bad inputs
-        let formats = [
-            ("focused", &user_formats.focused),
-            ("urgent", &user_formats.urgent),
-            ("default", &user_formats.default),
-        ];
-        for (name, format_opt) in formats {
-            if let Some(format) = format_opt
-                && !format.contains("{icon}")
-            {
-                log::warn!(
-                    "window-icon-format.{} must contain the substring \"{{icon}}\", using default",
-                    name
-                );
-            }
-        }
+        let user_formats = UserWindowIconFormats {
+            focused: with_icon(&user_formats.focused),
+            urgent: with_icon(&user_formats.urgent),
+            active: with_icon(&user_formats.active),
+            floating: with_icon(&user_formats.floating),
+            default: with_icon(&user_formats.default),
+            unknown: BTreeMap::new(),
+        };
+
+        let default = user_formats
+            .default
+            .clone()
+            .unwrap_or_else(|| DEFAULT_FORMAT.to_string());
 
+        // Windows in any state look like any other window unless configured
+        // otherwise. States are usually styled with CSS classes instead.
         Self {
             focused: user_formats
                 .focused
                 .clone()
-                .unwrap_or_else(|| DEFAULT_FOCUSED_FORMAT.to_string()),
+                .unwrap_or_else(|| default.clone()),
             urgent: user_formats
                 .urgent
                 .clone()
-                .unwrap_or_else(|| DEFAULT_URGENT_FORMAT.to_string()),
-            default: user_formats
-                .default
+                .unwrap_or_else(|| default.clone()),
+            active: user_formats
+                .active
                 .clone()
-                .unwrap_or_else(|| DEFAULT_FORMAT.to_string()),
+                .unwrap_or_else(|| default.clone()),
+            floating: user_formats
+                .floating
+                .clone()
+                .unwrap_or_else(|| default.clone()),
+            default,
+        }
+    }
+}
+
+fn with_icon(format: &Option<String>) -> Option<String> {
+    format.clone().filter(|format| format.contains("{icon}"))
+}
+
+impl Default for WindowIconFormats {
+    fn default() -> Self {
+        Self {
+            focused: DEFAULT_FORMAT.to_string(),
+            urgent: DEFAULT_FORMAT.to_string(),
+            active: DEFAULT_FORMAT.to_string(),
+            floating: DEFAULT_FORMAT.to_string(),
+            default: DEFAULT_FORMAT.to_string(),
         }
     }
 }
 
-// TODO: can active vs urgent styling be done with css instead of a config option?
 #[derive(Debug, Clone)]
 struct Config {
     format: String,
     window_icon_default: String,
     window_icon_formats: WindowIconFormats,
-    /// Merged icons: default icons + user-provided icons (user icons take precedence)
+    /// Merged icons: default icons + `window-icons-file` + user-provided icons
+    /// (user icons take precedence)
     window_icons: HashMap<String, String>,
+    /// Icons from `window-icons`, kept to merge again when the file changes
+    user_window_icons: HashMap<String, String>,
+    window_icons_file: Option<std::path::PathBuf>,
+    /// Inserted between icons of windows in the same column
+    icon_separator: String,
+    /// Inserted between icons of windows in different columns
+    column_separator: String,
+    /// Substituted for {separator} in `format` when the workspace has windows
+    label_separator: String,
+    /// Wraps the icons of a column containing more than one window
+    column_format: String,
+    /// Wraps the icon of a column containing a single window
+    column_format_single: String,
+    /// Wraps the icons of all floating windows
+    floating_format: String,
+    floating_position: FloatingPosition,
+    display: Display,
+    /// Pixels drawn in the minimap per logical pixel of the layout
+    minimap_scale: f64,
+    /// If set, workspaces without windows are hidden
+    hide_empty: Option<HideEmpty>,
+    persistent_workspaces: Vec<PersistentWorkspace>,
+    sort_by: SortBy,
+    /// Render the workspaces of each output in their own box
+    group_by_output: bool,
+    /// Label shown at the start of each output's box
+    output_header_format: Option<String>,
+    /// Whether to follow a window dragged to another workspace
+    drag_window_focus: bool,
+    /// Name of each workspace button for screen readers
+    accessible_format: String,
+    /// Replaces `format` while niri's overview is open
+    overview_format: Option<String>,
+    click_focused: ClickFocused,
+    workspace_actions: actions::MouseActions,
+    window_icon_actions: actions::MouseActions,
+    /// File to record niri's events and replies to, for bug reports
+    record_events: Option<std::path::PathBuf>,
+    /// Minimum time between updates, to coalesce bursts of events
+    update_debounce: Duration,
+    /// How long workspaces and windows are shown as urgent
+    urgent_timeout: Option<Duration>,
+    /// Interval at which the `urgent-pulse` class is toggled on urgent
+    /// workspaces
+    urgent_pulse: Option<Duration>,
+}
+
+/// Merges the default icons, the icons from `window-icons-file` and the
+/// user-provided icons, in increasing order of precedence. All app_ids are
+/// lowercased for case-insensitive matching.
+fn merge_window_icons(
+    file_icons: &HashMap<String, String>,
+    user_icons: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    // Start with default icons (already lowercase)
+    let mut window_icons: HashMap<String, String> = default_icons::DEFAULT_ICONS
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    for icons in [file_icons, user_icons] {
+        window_icons.extend(icons.iter().map(|(k, v)| (k.to_lowercase(), v.clone())));
+    }
+    window_icons
+}
+
+/// A format whose icons are rendered as separate widgets, or None if its markup
+/// spans them and can't be rendered (`validate` reports it).
+fn split_format(format: &Option<String>, split_at: &str) -> Option<String> {
+    format
+        .clone()
+        .filter(|format| validate::splits_cleanly(format, split_at, validate::pango_markup))
 }
 
 impl Config {
+    /// Loads `window-icons-file`, if set, returning its icons. Failures are
+    /// logged, leaving only the inline `window-icons` in use.
+    pub fn load_window_icons_file(&mut self) -> Option<HashMap<String, String>> {
+        let path = self.window_icons_file.as_ref()?;
+        match icons_file::load(path) {
+            Ok(file_icons) => {
+                self.window_icons = merge_window_icons(&file_icons, &self.user_window_icons);
+                Some(file_icons)
+            }
+            Err(err) => {
+                log::error!("Failed to load {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
     pub fn from_user(uc: &UserConfig) -> Self {
-        // Start with default icons (already lowercase)
-        let mut window_icons: HashMap<String, String> = default_icons::DEFAULT_ICONS
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect();
+        let window_icons_file = uc.window_icons_file.as_deref().map(icons_file::expand_path);
+        let user_window_icons = uc.window_icons.clone().unwrap_or_default();
+        // `window-icons-file` is loaded later by `load_window_icons_file`,
+        // off the GTK thread
+        let window_icons = merge_window_icons(&HashMap::new(), &user_window_icons);
 
-        // Merge user-provided icons, overwriting defaults
-        // Lowercase all app_ids for case-insensitive matching
-        if let Some(ref user_icons) = uc.window_icons {
-            window_icons.extend(
-                user_icons
-                    .iter()
-                    .map(|(k, v)| (k.to_lowercase(), v.clone())),
-            );
-        }
+        let icon_separator = uc
+            .icon_separator
+            .clone()
+            .unwrap_or_else(|| DEFAULT_ICON_SEPARATOR.to_string());
 
         Self {
-            format: uc
-                .format
-                .clone()
+            format: split_format(&uc.format, "{window-icons}")
                 .unwrap_or_else(|| DEFAULT_WORKSPACE_FORMAT.to_string()),
             window_icon_default: uc.window_icon_default.clone().unwrap_or_default(),
             window_icon_formats: uc
                 .window_icon_formats
                 .as_ref()
                 .map(WindowIconFormats::from_user)
-                .unwrap_or_else(|| WindowIconFormats {
-                    focused: DEFAULT_FOCUSED_FORMAT.to_string(),
-                    urgent: DEFAULT_URGENT_FORMAT.to_string(),
-                    default: DEFAULT_FORMAT.to_string(),
-                }),
+                .unwrap_or_default(),
             window_icons,
+            user_window_icons,
+            window_icons_file,
+            column_separator: uc
+                .column_separator
+                .clone()
+                .unwrap_or_else(|| icon_separator.clone()),
+            icon_separator,
+            label_separator: uc
+                .label_separator
+                .clone()
+                .unwrap_or_else(|| DEFAULT_LABEL_SEPARATOR.to_string()),
+            column_format: split_format(&uc.column_format, "{icons}")
+                .unwrap_or_else(|| DEFAULT_COLUMN_FORMAT.to_string()),
+            column_format_single: split_format(&uc.column_format_single, "{icons}")
+                .unwrap_or_else(|| DEFAULT_COLUMN_FORMAT.to_string()),
+            floating_format: split_format(&uc.floating_format, "{icons}")
+                .unwrap_or_else(|| DEFAULT_COLUMN_FORMAT.to_string()),
+            floating_position: uc.floating_position.unwrap_or_default(),
+            display: uc.display.unwrap_or_default(),
+            minimap_scale: uc
+                .minimap_scale
+                .filter(|&scale| scale > 0.0)
+                .unwrap_or(DEFAULT_MINIMAP_SCALE),
+            hide_empty: uc.hide_empty.as_ref().and_then(HideEmpty::from_user),
+            persistent_workspaces: uc
+                .persistent_workspaces
+                .as_ref()
+                .map(PersistentWorkspace::from_user)
+                .unwrap_or_default(),
+            sort_by: uc.sort_by.unwrap_or_default(),
+            group_by_output: uc.group_by_output.unwrap_or(false),
+            output_header_format: uc.output_header_format.clone(),
+            drag_window_focus: uc.drag_window_focus.unwrap_or(false),
+            accessible_format: uc
+                .accessible_format
+                .clone()
+                .unwrap_or_else(|| DEFAULT_ACCESSIBLE_FORMAT.to_string()),
+            overview_format: split_format(&uc.overview_format, "{window-icons}"),
+            click_focused: uc.click_focused.unwrap_or_default(),
+            workspace_actions: actions::MouseActions::workspace_from_user(
+                uc.workspace_actions.as_ref(),
+            ),
+            window_icon_actions: actions::MouseActions::window_from_user(
+                uc.window_icon_actions.as_ref(),
+            ),
+            record_events: uc.record_events.as_deref().map(icons_file::expand_path),
+            update_debounce: Duration::from_millis(
+                uc.update_debounce.unwrap_or(DEFAULT_UPDATE_DEBOUNCE_MS),
+            ),
+            urgent_timeout: uc
+                .urgent_timeout
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok()),
+            urgent_pulse: uc
+                .urgent_pulse
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
         }
     }
 }
@@ -417,12 +2210,48 @@ mod tests {
             window_icon_formats: WindowIconFormats {
                 focused: "{icon}".to_string(),
                 urgent: "{icon}".to_string(),
+                active: "{icon}".to_string(),
+                floating: "{icon}".to_string(),
                 default: "{icon}".to_string(),
             },
             window_icons: HashMap::new(),
+            user_window_icons: HashMap::new(),
+            window_icons_file: None,
+            icon_separator: " ".to_string(),
+            column_separator: " ".to_string(),
+            label_separator: ": ".to_string(),
+            column_format: "{icons}".to_string(),
+            column_format_single: "{icons}".to_string(),
+            floating_format: "{icons}".to_string(),
+            floating_position: FloatingPosition::Before,
+            display: Display::Icons,
+            minimap_scale: DEFAULT_MINIMAP_SCALE,
+            hide_empty: None,
+            persistent_workspaces: Vec::new(),
+            sort_by: SortBy::OutputThenIdx,
+            group_by_output: false,
+            output_header_format: None,
+            drag_window_focus: false,
+            accessible_format: DEFAULT_ACCESSIBLE_FORMAT.to_string(),
+            overview_format: None,
+            click_focused: ClickFocused::Focus,
+            workspace_actions: actions::MouseActions::workspace_from_user(None),
+            window_icon_actions: actions::MouseActions::window_from_user(None),
+            record_events: None,
+            update_debounce: Duration::from_millis(DEFAULT_UPDATE_DEBOUNCE_MS),
+            urgent_timeout: None,
+            urgent_pulse: None,
         }
     }
 
+    pub fn create_test_window_in_column(id: u64, column: Option<usize>) -> Window {
+        let mut window = create_test_window(Some("firefox".to_string()));
+        window.id = id;
+        window.layout.pos_in_scrolling_layout = column.map(|col| (col, 1));
+        window.is_floating = column.is_none();
+        window
+    }
+
     #[test]
     fn test_format_workspace_label_basic() {
         let cfg = create_default_config();
@@ -434,6 +2263,7 @@ mod tests {
             is_focused: false,
             is_urgent: false,
             is_active: false,
+            ..Default::default()
         };
         assert_eq!(format_workspace_label(&cfg, &info), "1");
     }
@@ -449,6 +2279,7 @@ mod tests {
             is_focused: false,
             is_urgent: false,
             is_active: false,
+            ..Default::default()
         };
         assert_eq!(format_workspace_label(&cfg, &info), "2 Work");
     }
@@ -464,6 +2295,7 @@ mod tests {
             is_focused: false,
             is_urgent: false,
             is_active: false,
+            ..Default::default()
         };
         assert_eq!(format_workspace_label(&cfg, &info), "3: 🔥 💻");
     }
@@ -479,10 +2311,698 @@ mod tests {
             is_focused: false,
             is_urgent: false,
             is_active: false,
+            ..Default::default()
         };
         assert_eq!(format_workspace_label(&cfg, &info), "4 Dev: 🚀");
     }
 
+    #[test]
+    fn test_format_workspace_label_custom_separator() {
+        let cfg = Config {
+            label_separator: " | ".to_string(),
+            ..create_default_config()
+        };
+        let info = WorkspaceInfo {
+            id: 1,
+            icons: "🚀".to_string(),
+            idx: 5,
+            ..Default::default()
+        };
+        assert_eq!(format_workspace_label(&cfg, &info), "5 | 🚀");
+    }
+
+    #[test]
+    fn test_workspace_label_pieces() {
+        let cfg = Config {
+            format: "<b>{index}</b>{separator}{window-icons}!".to_string(),
+            ..create_default_config()
+        };
+        let window = |id| IconPiece::Window {
+            id,
+            markup: "F".to_string(),
+            classes: vec!["window".to_string()],
+        };
+        let icon_pieces = vec![window(10), IconPiece::Markup(" ".to_string()), window(11)];
+        let info = WorkspaceInfo {
+            id: 1,
+            name: "{index}".to_string(),
+            icons: icons_markup(&icon_pieces),
+            icon_pieces,
+            idx: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            workspace_label_pieces(&cfg, &info),
+            vec![
+                IconPiece::Markup("<b>2</b>: ".to_string()),
+                window(10),
+                IconPiece::Markup(" ".to_string()),
+                window(11),
+                IconPiece::Markup("!".to_string()),
+            ]
+        );
+        assert_eq!(format_workspace_label(&cfg, &info), "<b>2</b>: F F!");
+
+        // Placeholders in the name aren't substituted again
+        let cfg = Config {
+            format: "{name} {index}".to_string(),
+            ..create_default_config()
+        };
+        assert_eq!(format_workspace_label(&cfg, &info), "{index} 2");
+    }
+
+    #[test]
+    fn test_from_user_rejects_markup_spanning_icons() {
+        let user_config: UserConfig = serde_json::from_str(
+            r#"{
+                "format": "<b>{index} {window-icons}</b>",
+                "overview-format": "<i>{index}</i> {window-icons}",
+                "column-format": "<span alpha='50%'>[{icons}]</span>",
+                "floating-format": "<i>{icons}</i>"
+            }"#,
+        )
+        .unwrap();
+        let config = Config::from_user(&user_config);
+        assert_eq!(config.format, DEFAULT_WORKSPACE_FORMAT);
+        assert_eq!(
+            config.overview_format.as_deref(),
+            Some("<i>{index}</i> {window-icons}")
+        );
+        assert_eq!(config.column_format, DEFAULT_COLUMN_FORMAT);
+        assert_eq!(config.floating_format, DEFAULT_COLUMN_FORMAT);
+    }
+
+    #[test]
+    fn test_format_window_icons_separators() {
+        let mut window_icons = HashMap::new();
+        window_icons.insert("firefox".to_string(), "F".to_string());
+        let cfg = Config {
+            window_icons,
+            icon_separator: "".to_string(),
+            column_separator: "<span> </span>".to_string(),
+            ..create_default_config()
+        };
+        let windows = [
+            create_test_window_in_column(1, Some(1)),
+            create_test_window_in_column(2, Some(1)),
+            create_test_window_in_column(3, Some(2)),
+            create_test_window_in_column(4, None),
+        ];
+        let windows: Vec<&Window> = windows.iter().collect();
+        assert_eq!(
+            icons_markup(&format_window_icons(&cfg, &windows, None)),
+            "F<span> </span>FF<span> </span>F"
+        );
+    }
+
+    #[test]
+    fn test_format_window_icons_column_format() {
+        let mut window_icons = HashMap::new();
+        window_icons.insert("firefox".to_string(), "F".to_string());
+        let cfg = Config {
+            window_icons,
+            column_format: "[{icons}]{count}".to_string(),
+            column_format_single: "({icons})".to_string(),
+            ..create_default_config()
+        };
+        let windows = [
+            create_test_window_in_column(1, None),
+            create_test_window_in_column(2, Some(1)),
+            create_test_window_in_column(3, Some(2)),
+            create_test_window_in_column(4, Some(2)),
+            create_test_window_in_column(5, Some(2)),
+        ];
+        let windows: Vec<&Window> = windows.iter().collect();
+        assert_eq!(
+            icons_markup(&format_window_icons(&cfg, &windows, None)),
+            "F (F) [F F F]3"
+        );
+    }
+
+    #[test]
+    fn test_format_window_icons_icon_with_placeholders() {
+        let mut window_icons = HashMap::new();
+        window_icons.insert("firefox".to_string(), "{count}{icons}".to_string());
+        let cfg = Config {
+            window_icons,
+            column_format: "{count}:{icons}".to_string(),
+            ..create_default_config()
+        };
+        let windows = [
+            create_test_window_in_column(1, Some(1)),
+            create_test_window_in_column(2, Some(1)),
+        ];
+        let windows: Vec<&Window> = windows.iter().collect();
+        assert_eq!(
+            icons_markup(&format_window_icons(&cfg, &windows, None)),
+            "2:{count}{icons} {count}{icons}"
+        );
+    }
+
+    #[test]
+    fn test_format_window_icons_floating_group() {
+        let mut window_icons = HashMap::new();
+        window_icons.insert("firefox".to_string(), "F".to_string());
+        window_icons.insert("mpv".to_string(), "M".to_string());
+        let mut cfg = Config {
+            window_icons,
+            floating_format: "<{icons}>".to_string(),
+            ..create_default_config()
+        };
+        let mut floating_a = create_test_window_in_column(1, None);
+        floating_a.app_id = Some("mpv".to_string());
+        let mut floating_b = create_test_window_in_column(2, None);
+        floating_b.app_id = Some("mpv".to_string());
+        let windows = [
+            floating_a,
+            floating_b,
+            create_test_window_in_column(3, Some(1)),
+            create_test_window_in_column(4, Some(2)),
+        ];
+        let windows: Vec<&Window> = windows.iter().collect();
+        assert_eq!(
+            icons_markup(&format_window_icons(&cfg, &windows, None)),
+            "<M M> F F"
+        );
+
+        cfg.floating_position = FloatingPosition::After;
+        assert_eq!(
+            icons_markup(&format_window_icons(&cfg, &windows, None)),
+            "F F <M M>"
+        );
+    }
+
+    fn create_test_workspace(id: u64, idx: u8, output: &str, window_count: usize) -> WorkspaceInfo {
+        WorkspaceInfo {
+            id,
+            idx,
+            output: Some(output.to_string()),
+            window_count,
+            ..Default::default()
+        }
+    }
+
+    fn ids(ws_info: &[WorkspaceInfo]) -> Vec<u64> {
+        ws_info.iter().map(|info| info.id).collect()
+    }
+
+    #[test]
+    fn test_hide_empty_workspaces() {
+        let mut focused = create_test_workspace(2, 2, "DP-1", 0);
+        focused.is_focused = true;
+        let mut named = create_test_workspace(4, 1, "HDMI-A-1", 0);
+        named.name = "chat".to_string();
+        let ws_info = vec![
+            create_test_workspace(1, 1, "DP-1", 2),
+            focused,
+            create_test_workspace(3, 3, "DP-1", 0),
+            named,
+            create_test_workspace(5, 2, "HDMI-A-1", 1),
+            create_test_workspace(6, 3, "HDMI-A-1", 0),
+        ];
+
+        let hide_empty = HideEmpty::default();
+        assert_eq!(
+            ids(&hide_empty_workspaces(&hide_empty, ws_info.clone())),
+            vec![1, 2, 5]
+        );
+
+        let hide_empty = HideEmpty {
+            keep_focused: false,
+            keep_named: true,
+            keep_trailing: true,
+        };
+        assert_eq!(
+            ids(&hide_empty_workspaces(&hide_empty, ws_info)),
+            vec![1, 3, 4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn test_hide_empty_from_user() {
+        assert_eq!(HideEmpty::from_user(&UserHideEmpty::Enabled(false)), None);
+        assert_eq!(
+            HideEmpty::from_user(&UserHideEmpty::Enabled(true)),
+            Some(HideEmpty::default())
+        );
+        let options = UserHideEmptyOptions {
+            keep_focused: None,
+            keep_named: Some(true),
+            keep_trailing: None,
+            unknown: BTreeMap::new(),
+        };
+        assert_eq!(
+            HideEmpty::from_user(&UserHideEmpty::Options(options)),
+            Some(HideEmpty {
+                keep_focused: true,
+                keep_named: true,
+                keep_trailing: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_add_persistent_workspaces() {
+        let mut web = create_test_workspace(1, 1, "DP-1", 1);
+        web.name = "Web".to_string();
+        let mut ws_info = vec![
+            web,
+            create_test_workspace(2, 2, "DP-1", 0),
+            create_test_workspace(3, 1, "HDMI-A-1", 0),
+        ];
+        let persistent = [
+            PersistentWorkspace {
+                name: "web".to_string(),
+                output: None,
+                spawn: None,
+            },
+            PersistentWorkspace {
+                name: "chat".to_string(),
+                output: Some("HDMI-A-1".to_string()),
+                spawn: Some(vec!["discord".to_string()]),
+            },
+            PersistentWorkspace {
+                name: "code".to_string(),
+                output: None,
+                spawn: None,
+            },
+        ];
+
+        add_persistent_workspaces(&persistent, &mut ws_info);
+
+        let placeholders: Vec<(&str, u8, Option<&str>)> = ws_info
+            .iter()
+            .filter(|info| info.is_placeholder)
+            .map(|info| (info.name.as_str(), info.idx, info.output.as_deref()))
+            .collect();
+        // "web" already exists on DP-1, and "code" is only shown once
+        assert_eq!(
+            placeholders,
+            vec![("chat", 2, Some("HDMI-A-1")), ("code", 3, Some("DP-1"))]
+        );
+
+        let placeholder_ids = ids(&ws_info[3..]);
+        assert_eq!(placeholder_ids, vec![u64::MAX - 1, u64::MAX - 2]);
+
+        // Ids stay the same once a placeholder's workspace exists
+        let mut chat = create_test_workspace(4, 2, "HDMI-A-1", 0);
+        chat.name = "chat".to_string();
+        let mut next_info = ws_info[..3].to_vec();
+        next_info.push(chat);
+        add_persistent_workspaces(&persistent, &mut next_info);
+        assert_eq!(ids(&next_info[4..]), placeholder_ids[1..]);
+
+        // Shown on the focused output
+        let mut ws_info = vec![
+            create_test_workspace(1, 1, "DP-1", 0),
+            create_test_workspace(3, 1, "HDMI-A-1", 0),
+        ];
+        ws_info[1].is_focused = true;
+        add_persistent_workspaces(&persistent[2..], &mut ws_info);
+        assert_eq!(ws_info[2].output.as_deref(), Some("HDMI-A-1"));
+        assert_eq!(ws_info[2].idx, 2);
+    }
+
+    #[test]
+    fn test_add_persistent_workspaces_without_outputs() {
+        let persistent = [PersistentWorkspace {
+            name: "web".to_string(),
+            output: None,
+            spawn: None,
+        }];
+        let mut ws_info = Vec::new();
+        add_persistent_workspaces(&persistent, &mut ws_info);
+        assert_eq!(ws_info.len(), 1);
+        assert_eq!(ws_info[0].output, None);
+        assert_eq!(ws_info[0].idx, 1);
+    }
+
+    #[test]
+    fn test_persistent_workspaces_from_user() {
+        let user: UserPersistentWorkspaces = serde_json::from_str(
+            r#"{"DP-1": ["web", {"name": "code", "spawn": ["code"]}], "HDMI-A-1": ["chat"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            PersistentWorkspace::from_user(&user),
+            vec![
+                PersistentWorkspace {
+                    name: "web".to_string(),
+                    output: Some("DP-1".to_string()),
+                    spawn: None,
+                },
+                PersistentWorkspace {
+                    name: "code".to_string(),
+                    output: Some("DP-1".to_string()),
+                    spawn: Some(vec!["code".to_string()]),
+                },
+                PersistentWorkspace {
+                    name: "chat".to_string(),
+                    output: Some("HDMI-A-1".to_string()),
+                    spawn: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_workspaces() {
+        let mut web = create_test_workspace(5, 2, "HDMI-A-1", 0);
+        web.name = "web".to_string();
+        let mut chat = create_test_workspace(1, 2, "DP-1", 0);
+        chat.name = "Chat".to_string();
+        let mut ws_info = vec![
+            web,
+            create_test_workspace(3, 1, "HDMI-A-1", 0),
+            chat,
+            create_test_workspace(2, 1, "DP-1", 0),
+            create_test_workspace(4, 3, "DP-1", 0),
+        ];
+        let outputs = [
+            OutputInfo {
+                name: "DP-1".to_string(),
+                position: Some((1920, 0)),
+                ..Default::default()
+            },
+            OutputInfo {
+                name: "HDMI-A-1".to_string(),
+                position: Some((0, 0)),
+                ..Default::default()
+            },
+        ];
+
+        sort_workspaces(SortBy::OutputThenIdx, &mut ws_info, &outputs);
+        assert_eq!(ids(&ws_info), vec![2, 1, 4, 3, 5]);
+
+        sort_workspaces(SortBy::Idx, &mut ws_info, &outputs);
+        assert_eq!(ids(&ws_info), vec![2, 3, 1, 5, 4]);
+
+        sort_workspaces(SortBy::Name, &mut ws_info, &outputs);
+        assert_eq!(ids(&ws_info), vec![1, 5, 2, 4, 3]);
+
+        sort_workspaces(SortBy::Id, &mut ws_info, &outputs);
+        assert_eq!(ids(&ws_info), vec![1, 2, 3, 4, 5]);
+
+        sort_workspaces(SortBy::OutputPosition, &mut ws_info, &outputs);
+        assert_eq!(ids(&ws_info), vec![3, 5, 2, 1, 4]);
+    }
+
+    #[test]
+    fn test_group_by_output() {
+        let ws_info = vec![
+            create_test_workspace(1, 1, "DP-1", 0),
+            create_test_workspace(2, 1, "HDMI-A-1", 0),
+            create_test_workspace(3, 2, "DP-1", 0),
+        ];
+        let groups: Vec<(Option<String>, Vec<u64>)> = group_by_output(ws_info)
+            .into_iter()
+            .map(|(output, ws_info)| (output, ids(&ws_info)))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (Some("DP-1".to_string()), vec![1, 3]),
+                (Some("HDMI-A-1".to_string()), vec![2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_output_header() {
+        let info = OutputInfo {
+            name: "DP-1".to_string(),
+            make: "Dell & Co".to_string(),
+            model: "U2720Q".to_string(),
+            position: None,
+        };
+        assert_eq!(
+            format_output_header(
+                "{output}: {output-make} {output-model}",
+                Some("DP-1"),
+                Some(&info)
+            ),
+            "DP-1: Dell &amp; Co U2720Q"
+        );
+        assert_eq!(format_output_header("[{output}]", None, None), "[]");
+    }
+
+    #[test]
+    fn test_css_class_name() {
+        assert_eq!(css_class_name("HDMI-A-1"), "hdmi-a-1");
+        assert_eq!(css_class_name("org.gnome.Nautilus"), "org-gnome-nautilus");
+    }
+
+    #[test]
+    fn test_format_window_icons_pieces() {
+        let mut window_icons = HashMap::new();
+        window_icons.insert("firefox".to_string(), "F".to_string());
+        let cfg = Config {
+            window_icons,
+            column_format: "[{icons}]".to_string(),
+            ..create_default_config()
+        };
+        let windows = [
+            create_test_window_in_column(1, Some(1)),
+            create_test_window_in_column(2, Some(1)),
+            create_test_window_in_column(3, Some(2)),
+        ];
+        let windows: Vec<&Window> = windows.iter().collect();
+        let window = |id| IconPiece::Window {
+            id,
+            markup: "F".to_string(),
+            classes: vec!["window".to_string(), "app-firefox".to_string()],
+        };
+        assert_eq!(
+            format_window_icons(&cfg, &windows, None),
+            vec![
+                IconPiece::Markup("[".to_string()),
+                window(1),
+                IconPiece::Markup(" ".to_string()),
+                window(2),
+                IconPiece::Markup("] ".to_string()),
+                window(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_workspace_drop_actions() {
+        let target = create_test_workspace(2, 3, "DP-1", 0);
+
+        let actions = workspace_drop_actions(1, Some("DP-1"), &target);
+        assert!(matches!(
+            actions.as_slice(),
+            [niri_ipc::Action::MoveWorkspaceToIndex {
+                index: 3,
+                reference: Some(niri_ipc::WorkspaceReferenceArg::Id(1)),
+            }]
+        ));
+
+        let actions = workspace_drop_actions(1, Some("HDMI-A-1"), &target);
+        assert!(matches!(
+            actions.as_slice(),
+            [
+                niri_ipc::Action::MoveWorkspaceToMonitor { output, .. },
+                niri_ipc::Action::MoveWorkspaceToIndex { index: 3, .. },
+            ] if output == "DP-1"
+        ));
+
+        assert!(workspace_drop_actions(2, Some("DP-1"), &target).is_empty());
+    }
+
+    #[test]
+    fn test_format_accessible_name() {
+        let mut info = WorkspaceInfo {
+            idx: 2,
+            name: "Work".to_string(),
+            window_count: 3,
+            is_focused: true,
+            is_active: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_accessible_name("Workspace {index-and-name}, {windows}, {state}", &info),
+            "Workspace 2 Work, 3 windows, focused, active"
+        );
+
+        info.window_count = 1;
+        assert_eq!(
+            format_accessible_name(DEFAULT_ACCESSIBLE_FORMAT, &info),
+            "Workspace 2 Work, 1 window"
+        );
+
+        info.window_count = 0;
+        assert_eq!(
+            format_accessible_name("{value}: {windows}", &info),
+            "Work: no windows"
+        );
+    }
+
+    #[test]
+    fn test_refocus_index() {
+        assert_eq!(refocus_index(&[1, 2, 3], 2, 1), Some(1));
+        // The workspace moved
+        assert_eq!(refocus_index(&[2, 1, 3], 2, 1), Some(0));
+        // The workspace is gone
+        assert_eq!(refocus_index(&[1, 3], 2, 1), Some(1));
+        assert_eq!(refocus_index(&[1], 3, 2), Some(0));
+        assert_eq!(refocus_index(&[], 3, 2), None);
+    }
+
+    #[test]
+    fn test_next_focus_index() {
+        use gdk::keys::constants as keys;
+        assert_eq!(next_focus_index(1, 3, keys::Left), Some(0));
+        assert_eq!(next_focus_index(1, 3, keys::Down), Some(2));
+        assert_eq!(next_focus_index(0, 3, keys::Up), None);
+        assert_eq!(next_focus_index(2, 3, keys::Right), None);
+        assert_eq!(next_focus_index(1, 3, keys::Home), Some(0));
+        assert_eq!(next_focus_index(1, 3, keys::End), Some(2));
+        assert_eq!(next_focus_index(2, 3, keys::End), None);
+        assert_eq!(next_focus_index(1, 3, keys::a), None);
+    }
+
+    #[test]
+    fn test_focus_history() {
+        let mut history = FocusHistory::default();
+        history.focus(1);
+        assert_eq!(history.previous, None);
+
+        // Focusing the same workspace again doesn't forget the previous one
+        history.focus(2);
+        history.focus(2);
+        assert_eq!(
+            history,
+            FocusHistory {
+                current: Some(2),
+                previous: Some(1),
+            }
+        );
+
+        history.focus(1);
+        assert_eq!(history.previous, Some(2));
+    }
+
+    #[test]
+    fn test_urgency_times() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let five_secs = Some(Duration::from_secs(5));
+        // Returns the ids still shown as urgent
+        let expire = |times: &mut UrgencyTimes, urgent: &[u64], timeout, now| {
+            let mut workspaces: Vec<niri_ipc::Workspace> = [1, 2]
+                .into_iter()
+                .map(|id| niri_ipc::Workspace {
+                    id,
+                    idx: id as u8,
+                    name: None,
+                    output: None,
+                    is_urgent: urgent.contains(&id),
+                    is_active: false,
+                    is_focused: false,
+                    active_window_id: None,
+                })
+                .collect();
+            times.expire(&mut workspaces, &mut [], timeout, now);
+            workspaces
+                .iter()
+                .filter(|ws| ws.is_urgent)
+                .map(|ws| ws.id)
+                .collect::<Vec<u64>>()
+        };
+
+        let mut times = UrgencyTimes::default();
+        assert_eq!(expire(&mut times, &[1], five_secs, secs(0)), vec![1]);
+        assert_eq!(expire(&mut times, &[1, 2], five_secs, secs(3)), vec![1, 2]);
+        assert_eq!(
+            times.next_expiry(Duration::from_secs(5), secs(3)),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(expire(&mut times, &[1, 2], five_secs, secs(5)), vec![2]);
+        assert!(expire(&mut times, &[1, 2], five_secs, secs(8)).is_empty());
+        assert_eq!(times.next_expiry(Duration::from_secs(5), secs(8)), None);
+
+        // Becoming urgent again starts a new timeout
+        expire(&mut times, &[], five_secs, secs(9));
+        assert_eq!(expire(&mut times, &[1], five_secs, secs(10)), vec![1]);
+
+        // Without a timeout, urgency never expires
+        let mut times = UrgencyTimes::default();
+        expire(&mut times, &[1], None, secs(0));
+        assert_eq!(expire(&mut times, &[1], None, secs(100)), vec![1]);
+    }
+
+    #[test]
+    fn test_debouncer() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut debouncer = Debouncer::new(Duration::from_millis(20));
+
+        // The first change is sent right away
+        assert!(debouncer.changed(ms(0)));
+        assert_eq!(debouncer.timeout(ms(0)), None);
+
+        // A burst is coalesced into one update at the end of the interval
+        assert!(!debouncer.changed(ms(5)));
+        assert!(!debouncer.changed(ms(10)));
+        assert_eq!(debouncer.timeout(ms(10)), Some(Duration::from_millis(10)));
+        assert_eq!(debouncer.timeout(ms(30)), Some(Duration::ZERO));
+        debouncer.updated(ms(30));
+        assert_eq!(debouncer.timeout(ms(30)), None);
+
+        // After a quiet period, changes are sent right away again
+        assert!(debouncer.changed(ms(100)));
+
+        // A zero interval never delays
+        let mut debouncer = Debouncer::new(Duration::ZERO);
+        assert!(debouncer.changed(ms(0)));
+        assert!(debouncer.changed(ms(0)));
+    }
+
+    #[test]
+    fn test_merge_window_icons() {
+        let file_icons = HashMap::from([
+            ("Firefox".to_string(), "file".to_string()),
+            ("foot".to_string(), "file".to_string()),
+        ]);
+        let user_icons = HashMap::from([("FOOT".to_string(), "user".to_string())]);
+        let icons = merge_window_icons(&file_icons, &user_icons);
+        assert_eq!(icons.get("firefox").map(String::as_str), Some("file"));
+        assert_eq!(icons.get("foot").map(String::as_str), Some("user"));
+        assert!(icons.contains_key("firefox-esr"));
+    }
+
+    #[test]
+    fn test_window_icon_formats_without_icon_use_default() {
+        let user_formats: UserWindowIconFormats = serde_json::from_str(
+            r#"{ "focused": "<b>focused</b>", "default": "[{icon}]", "floating": "float" }"#,
+        )
+        .unwrap();
+        let formats = WindowIconFormats::from_user(&user_formats);
+        assert_eq!(formats.focused, "[{icon}]");
+        assert_eq!(formats.default, "[{icon}]");
+        assert_eq!(formats.floating, "[{icon}]");
+    }
+
+    #[test]
+    fn test_smooth_scroll_event() {
+        let mut scrolled = 0.0;
+        assert_eq!(smooth_scroll_event(&mut scrolled, 0.4), None);
+        assert_eq!(smooth_scroll_event(&mut scrolled, 0.4), None);
+        assert_eq!(
+            smooth_scroll_event(&mut scrolled, 0.4),
+            Some(MouseEvent::ScrollDown)
+        );
+        assert_eq!(smooth_scroll_event(&mut scrolled, 0.4), None);
+
+        // Scrolling back starts over
+        assert_eq!(smooth_scroll_event(&mut scrolled, -0.8), None);
+        assert_eq!(
+            smooth_scroll_event(&mut scrolled, -0.4),
+            Some(MouseEvent::ScrollUp)
+        );
+    }
+
     #[test]
     fn test_format_icon_default() {
         let config = Config {
@@ -491,11 +3011,15 @@ mod tests {
             window_icon_formats: WindowIconFormats {
                 focused: "{icon}".to_string(),
                 urgent: "{icon}".to_string(),
+                active: "{icon}".to_string(),
+                floating: "{icon}".to_string(),
                 default: "{icon}".to_string(),
             },
             window_icons: HashMap::new(),
+            ..create_default_config()
         };
-        let result = format_icon(&config, "🔥", false, false);
+        let window = create_test_window(Some("firefox".to_string()));
+        let result = format_icon(&config, "🔥", &window, 1, WindowState::Default);
         assert_eq!(result, "🔥");
     }
 
@@ -504,6 +3028,8 @@ mod tests {
         let formats = WindowIconFormats {
             focused: "[{icon}]".to_string(),
             urgent: "{icon}".to_string(),
+            active: "{icon}".to_string(),
+            floating: "{icon}".to_string(),
             default: "{icon}".to_string(),
         };
         let config = Config {
@@ -511,8 +3037,12 @@ mod tests {
             window_icon_default: String::new(),
             window_icon_formats: formats,
             window_icons: HashMap::new(),
+            ..create_default_config()
         };
-        let result = format_icon(&config, "🔥", true, false);
+        let mut window = create_test_window(Some("firefox".to_string()));
+        window.is_focused = true;
+        let state = WindowState::of(&window, None);
+        let result = format_icon(&config, "🔥", &window, 1, state);
         assert_eq!(result, "[🔥]");
     }
 
@@ -521,6 +3051,8 @@ mod tests {
         let formats = WindowIconFormats {
             focused: "[{icon}]".to_string(),
             urgent: "!{icon}!".to_string(),
+            active: "{icon}".to_string(),
+            floating: "{icon}".to_string(),
             default: "{icon}".to_string(),
         };
         let config = Config {
@@ -528,11 +3060,111 @@ mod tests {
             window_icon_default: String::new(),
             window_icon_formats: formats,
             window_icons: HashMap::new(),
+            ..create_default_config()
         };
-        let result = format_icon(&config, "🔥", true, true);
+        let mut window = create_test_window(Some("firefox".to_string()));
+        window.is_focused = true;
+        window.is_urgent = true;
+        let state = WindowState::of(&window, None);
+        let result = format_icon(&config, "🔥", &window, 1, state);
         assert_eq!(result, "!🔥!");
     }
 
+    #[test]
+    fn test_format_icon_window_placeholders() {
+        let config = Config {
+            format: String::new(),
+            window_icon_default: String::new(),
+            window_icon_formats: WindowIconFormats {
+                focused: "{icon}".to_string(),
+                urgent: "{icon}".to_string(),
+                active: "{icon}".to_string(),
+                floating: "{icon}".to_string(),
+                default: "{index}:{icon} {title} ({app_id}, {pid})".to_string(),
+            },
+            window_icons: HashMap::new(),
+            ..create_default_config()
+        };
+        let mut window = create_test_window(Some("firefox".to_string()));
+        window.title = Some("Tom & Jerry <1>".to_string());
+        window.pid = Some(42);
+        let result = format_icon(&config, "🔥", &window, 3, WindowState::Default);
+        assert_eq!(result, "3:🔥 Tom &amp; Jerry &lt;1&gt; (firefox, 42)");
+    }
+
+    #[test]
+    fn test_format_icon_title_with_placeholders() {
+        let config = Config {
+            window_icon_formats: WindowIconFormats {
+                focused: "{icon}".to_string(),
+                urgent: "{icon}".to_string(),
+                active: "{icon}".to_string(),
+                floating: "{icon}".to_string(),
+                default: "{title} {index}".to_string(),
+            },
+            ..create_default_config()
+        };
+        let mut window = create_test_window(Some("firefox".to_string()));
+        window.title = Some("foo {index} {pid}".to_string());
+        let result = format_icon(&config, "🔥", &window, 3, WindowState::Default);
+        assert_eq!(result, "foo {index} {pid} 3");
+    }
+
+    #[test]
+    fn test_substitute() {
+        let value = |placeholder: &str| (placeholder == "a").then(|| "{a}".to_string());
+        assert_eq!(substitute("{a}-{b}-{{a}}-{a", value), "{a}-{b}-{{a}}-{a");
+        assert_eq!(substitute("x{a}y", value), "x{a}y");
+        let value = |placeholder: &str| (placeholder == "a").then(|| "1".to_string());
+        assert_eq!(substitute("{a}-{b}-{{a}}-{a", value), "1-{b}-{1}-{a");
+    }
+
+    #[test]
+    fn test_window_state_precedence() {
+        let mut window = create_test_window(Some("firefox".to_string()));
+        assert_eq!(WindowState::of(&window, None), WindowState::Default);
+
+        window.is_floating = true;
+        assert_eq!(WindowState::of(&window, None), WindowState::Floating);
+        assert_eq!(WindowState::of(&window, Some(2)), WindowState::Floating);
+
+        assert_eq!(WindowState::of(&window, Some(1)), WindowState::Active);
+
+        window.is_focused = true;
+        assert_eq!(WindowState::of(&window, Some(1)), WindowState::Focused);
+
+        window.is_urgent = true;
+        assert_eq!(WindowState::of(&window, Some(1)), WindowState::Urgent);
+    }
+
+    #[test]
+    fn test_window_classes() {
+        let mut window = create_test_window(Some("org.gnome.Nautilus".to_string()));
+        assert_eq!(
+            window_classes(&window, None),
+            vec!["window", "app-org-gnome-nautilus"]
+        );
+
+        // Unlike the icon formats, all states apply at once
+        window.is_focused = true;
+        window.is_urgent = true;
+        window.is_floating = true;
+        assert_eq!(
+            window_classes(&window, Some(1)),
+            vec![
+                "window",
+                "focused",
+                "urgent",
+                "active",
+                "floating",
+                "app-org-gnome-nautilus"
+            ]
+        );
+
+        window.app_id = None;
+        assert_eq!(window_classes(&window, Some(2)).last().unwrap(), "floating");
+    }
+
     #[test]
     fn test_get_raw_icon_with_mapping() {
         let mut window_icons = HashMap::new();
@@ -545,9 +3177,12 @@ mod tests {
             window_icon_formats: WindowIconFormats {
                 focused: "{icon}".to_string(),
                 urgent: "{icon}".to_string(),
+                active: "{icon}".to_string(),
+                floating: "{icon}".to_string(),
                 default: "{icon}".to_string(),
             },
             window_icons,
+            ..create_default_config()
         };
 
         let window = create_test_window(Some("Firefox".to_string()));
@@ -563,9 +3198,12 @@ mod tests {
             window_icon_formats: WindowIconFormats {
                 focused: "{icon}".to_string(),
                 urgent: "{icon}".to_string(),
+                active: "{icon}".to_string(),
+                floating: "{icon}".to_string(),
                 default: "{icon}".to_string(),
             },
             window_icons: HashMap::new(),
+            ..create_default_config()
         };
 
         let window = create_test_window(None);
@@ -584,9 +3222,12 @@ mod tests {
             window_icon_formats: WindowIconFormats {
                 focused: "{icon}".to_string(),
                 urgent: "{icon}".to_string(),
+                active: "{icon}".to_string(),
+                floating: "{icon}".to_string(),
                 default: "{icon}".to_string(),
             },
             window_icons,
+            ..create_default_config()
         };
 
         let window = create_test_window(Some("FIREFOX".to_string()));
@@ -601,6 +3242,7 @@ mod tests {
             window_icons: None,
             window_icon_default: None,
             window_icon_formats: None,
+            ..Default::default()
         };
 
         let config = Config::from_user(&user_config);
@@ -619,6 +3261,7 @@ mod tests {
             window_icons: Some(user_icons),
             window_icon_default: None,
             window_icon_formats: None,
+            ..Default::default()
         };
 
         let config = Config::from_user(&user_config);
@@ -640,6 +3283,7 @@ mod tests {
             window_icons: Some(user_icons),
             window_icon_default: None,
             window_icon_formats: None,
+            ..Default::default()
         };
 
         let config = Config::from_user(&user_config);
@@ -661,6 +3305,7 @@ mod tests {
             window_icons: None,
             window_icon_default: None,
             window_icon_formats: None,
+            ..Default::default()
         };
 
         let config = Config::from_user(&user_config);
@@ -673,8 +3318,10 @@ mod tests {
         // Should have default formats
         let formats = &config.window_icon_formats;
         assert_eq!(&formats.default, DEFAULT_FORMAT);
-        assert_eq!(&formats.focused, DEFAULT_FOCUSED_FORMAT);
-        assert_eq!(&formats.urgent, DEFAULT_URGENT_FORMAT);
+        assert_eq!(&formats.focused, DEFAULT_FORMAT);
+        assert_eq!(&formats.urgent, DEFAULT_FORMAT);
+        assert_eq!(&formats.active, DEFAULT_FORMAT);
+        assert_eq!(&formats.floating, DEFAULT_FORMAT);
     }
 
     #[test]
@@ -686,6 +3333,7 @@ mod tests {
             window_icons: Some(user_window_icons),
             window_icon_default: None,
             window_icon_formats: None,
+            ..Default::default()
         };
         let config = Config::from_user(&user_config);
         assert!(config.window_icons.contains_key("firefox"));
@@ -0,0 +1,24 @@
+//! Runs the module outside of waybar's CFFI loader, printing one JSON line per
+//! update or the i3bar protocol (see `json_output` for the format), or
+//! previews a config offline with `preview`.
+
+use std::process::ExitCode;
+use waybar_niri_workspaces_enhanced::{json_output, preview};
+
+fn main() -> ExitCode {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [command, rest @ ..] if command == "preview" => preview::run(rest),
+        args => json_output::run(args),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
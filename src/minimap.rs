@@ -0,0 +1,177 @@
+//! Minimap display mode: each workspace button draws its columns as boxes
+//! whose widths are proportional to their size in niri's scrolling layout.
+
+use niri_ipc::Window;
+use waybar_cffi::gtk::{
+    self,
+    traits::{StyleContextExt, WidgetExt},
+};
+
+/// Space in pixels between two columns
+const COLUMN_GAP: f64 = 2.0;
+/// Space in pixels between two tiles in the same column
+const TILE_GAP: f64 = 1.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinimapColumn {
+    /// Width of the column in logical pixels
+    pub width: f64,
+    /// Whether any part of the column is currently visible on its output
+    pub in_view: bool,
+    /// Tiles in the column, from top to bottom
+    pub tiles: Vec<MinimapTile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinimapTile {
+    /// Height of the tile in logical pixels
+    pub height: f64,
+    pub is_focused: bool,
+}
+
+/// Builds the minimap columns for a workspace from its windows, which must be
+/// sorted by their position in the scrolling layout. Floating windows are not
+/// part of any column and are skipped.
+pub fn columns_from_windows(windows: &[&Window]) -> Vec<MinimapColumn> {
+    let mut columns: Vec<(usize, MinimapColumn)> = Vec::new();
+    for window in windows {
+        let Some((col, _)) = window.layout.pos_in_scrolling_layout else {
+            continue;
+        };
+        let (width, height) = window.layout.tile_size;
+        let in_view = window.layout.tile_pos_in_workspace_view.is_some();
+        let tile = MinimapTile {
+            height,
+            is_focused: window.is_focused,
+        };
+
+        match columns.last_mut() {
+            Some((prev_col, column)) if *prev_col == col => {
+                column.width = column.width.max(width);
+                column.in_view |= in_view;
+                column.tiles.push(tile);
+            }
+            _ => columns.push((
+                col,
+                MinimapColumn {
+                    width,
+                    in_view,
+                    tiles: vec![tile],
+                },
+            )),
+        }
+    }
+    columns.into_iter().map(|(_, column)| column).collect()
+}
+
+/// Creates a widget that draws `columns`, scaling logical pixels by `scale`.
+/// Boxes are drawn in the widget's CSS `color`, so they can be styled with
+/// `#workspaces button .minimap { color: ...; }`.
+pub fn new_widget(columns: Vec<MinimapColumn>, scale: f64) -> gtk::DrawingArea {
+    let area = gtk::DrawingArea::new();
+    area.style_context().add_class("minimap");
+
+    let gaps = COLUMN_GAP * columns.len().saturating_sub(1) as f64;
+    let width = columns.iter().map(|c| c.width * scale).sum::<f64>() + gaps;
+    area.set_size_request(width.ceil() as i32, -1);
+
+    area.connect_draw(move |area, cr| {
+        let color = area.style_context().color(area.state_flags());
+        let height = f64::from(area.allocated_height());
+
+        let mut x = 0.0;
+        for column in &columns {
+            let column_width = column.width * scale;
+            let total_height: f64 = column.tiles.iter().map(|t| t.height).sum();
+            let tile_gaps = TILE_GAP * column.tiles.len().saturating_sub(1) as f64;
+            let usable_height = (height - tile_gaps).max(0.0);
+
+            let mut y = 0.0;
+            for tile in &column.tiles {
+                let tile_height = if total_height > 0.0 {
+                    tile.height / total_height * usable_height
+                } else {
+                    usable_height / column.tiles.len() as f64
+                };
+                let alpha = if tile.is_focused {
+                    1.0
+                } else if column.in_view {
+                    0.5
+                } else {
+                    0.2
+                };
+                cr.set_source_rgba(
+                    color.red(),
+                    color.green(),
+                    color.blue(),
+                    color.alpha() * alpha,
+                );
+                cr.rectangle(x, y, column_width, tile_height);
+                if let Err(err) = cr.fill() {
+                    log::warn!("Failed to draw minimap: {}", err);
+                }
+                y += tile_height + TILE_GAP;
+            }
+            x += column_width + COLUMN_GAP;
+        }
+
+        gtk::glib::Propagation::Proceed
+    });
+
+    area
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::create_test_window_in_column;
+
+    fn create_test_window(column: Option<usize>, tile_size: (f64, f64), in_view: bool) -> Window {
+        let mut window = create_test_window_in_column(1, column);
+        window.layout.tile_size = tile_size;
+        window.layout.tile_pos_in_workspace_view = in_view.then_some((0.0, 0.0));
+        window
+    }
+
+    #[test]
+    fn test_columns_from_windows() {
+        let mut focused = create_test_window(Some(2), (800.0, 300.0), false);
+        focused.is_focused = true;
+        let windows = [
+            create_test_window(Some(1), (1000.0, 600.0), false),
+            create_test_window(Some(2), (600.0, 300.0), true),
+            focused,
+            create_test_window(None, (400.0, 400.0), true),
+        ];
+        let windows: Vec<&Window> = windows.iter().collect();
+
+        let columns = columns_from_windows(&windows);
+        assert_eq!(
+            columns,
+            vec![
+                MinimapColumn {
+                    width: 1000.0,
+                    in_view: false,
+                    tiles: vec![MinimapTile {
+                        height: 600.0,
+                        is_focused: false,
+                    }],
+                },
+                MinimapColumn {
+                    width: 800.0,
+                    in_view: true,
+                    tiles: vec![
+                        MinimapTile {
+                            height: 300.0,
+                            is_focused: false,
+                        },
+                        MinimapTile {
+                            height: 300.0,
+                            is_focused: true,
+                        },
+                    ],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Offline preview of a config against saved niri state, for iterating on
+//! formats without restarting waybar:
+//!
+//! ```sh
+//! niri msg --json workspaces > workspaces.json
+//! niri msg --json windows > windows.json
+//! waybar-niri-workspaces-enhanced preview --config ~/.config/waybar/config.jsonc \
+//!     --workspaces workspaces.json --windows windows.json
+//! ```
+//!
+//! or against a recording made with `record-events`, printing every update.
+
+use crate::{
+    Config, EventState, IconPiece, Update, UserConfig, WorkspaceInfo, build_update,
+    displayed_workspaces, format_workspace_label, jsonc, overview_config, recording, validate,
+    workspace_label_pieces, workspace_states,
+};
+use std::collections::HashMap;
+
+pub(crate) const DEFAULT_MODULE: &str = "cffi/niri-workspaces-enhanced";
+
+pub const USAGE: &str = "\
+Usage: waybar-niri-workspaces-enhanced preview --config <waybar-config>
+           --workspaces <file> --windows <file> [--outputs <file>]
+           [--module <name>] [--overview]
+       waybar-niri-workspaces-enhanced preview --config <waybar-config>
+           --replay <recording> [--module <name>]
+
+Prints the label and CSS classes of each workspace. The files are the output
+of `niri msg --json workspaces`, `windows` and `outputs`. With --replay, the
+workspaces after each update in a recording made with \"record-events\" are
+printed, separated by blank lines.";
+
+/// Runs the `preview` command with the arguments following it.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files: HashMap<&str, String> = HashMap::new();
+    let mut overview_open = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--overview" => overview_open = true,
+            "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            flag @ ("--config" | "--workspaces" | "--windows" | "--outputs" | "--module"
+            | "--replay") => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", flag))?;
+                files.insert(flag, value.clone());
+            }
+            _ => return Err(format!("Unexpected argument {}\n\n{}", arg, USAGE).into()),
+        }
+    }
+    let read = |flag: &str| -> Result<Option<String>, Box<dyn std::error::Error>> {
+        match files.get(flag) {
+            Some(path) => std::fs::read_to_string(path)
+                .map(Some)
+                .map_err(|err| format!("Failed to read {}: {}", path, err).into()),
+            None => Ok(None),
+        }
+    };
+    let missing = |flag: &str| format!("Missing {}\n\n{}", flag, USAGE);
+
+    let waybar_config = read("--config")?.ok_or_else(|| missing("--config"))?;
+    let module = files.get("--module").map_or(DEFAULT_MODULE, String::as_str);
+    let user_config = user_config(&waybar_config, module)?;
+    for problem in validate::check(&user_config, validate::pango_markup) {
+        eprintln!("Invalid config: {}", problem);
+    }
+
+    if let Some(recording) = read("--replay")? {
+        let (tx, rx) = async_channel::unbounded();
+        recording::replay(
+            Config::from_user(&user_config),
+            &recording::parse(&recording)?,
+            &tx,
+        )?;
+        drop(tx);
+        let config = Config::from_user(&user_config);
+        let overview_config = overview_config(&config);
+        let updates: Vec<String> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|update| render_update(&config, overview_config.as_ref(), update))
+            .collect();
+        print!("{}", updates.join("\n"));
+        return Ok(());
+    }
+
+    let workspaces = read("--workspaces")?.ok_or_else(|| missing("--workspaces"))?;
+    let windows = read("--windows")?.ok_or_else(|| missing("--windows"))?;
+    let outputs = read("--outputs")?.unwrap_or_else(|| "{}".to_string());
+
+    let mut config = Config::from_user(&user_config);
+    config.load_window_icons_file();
+    let state = EventState {
+        overview_open,
+        ..Default::default()
+    };
+    let update = build_update(
+        &config,
+        &serde_json::from_str::<Vec<niri_ipc::Workspace>>(&workspaces)?,
+        serde_json::from_str(&windows)?,
+        serde_json::from_str(&outputs)?,
+        &state,
+    );
+    print!(
+        "{}",
+        render_update(&config, overview_config(&config).as_ref(), update)
+    );
+    Ok(())
+}
+
+fn render_update(config: &Config, overview_config: Option<&Config>, update: Update) -> String {
+    let config = match overview_config {
+        Some(overview_config) if update.overview_open => overview_config,
+        _ => config,
+    };
+    let ws_info = displayed_workspaces(config, update.workspaces, &update.outputs);
+    render(config, &ws_info)
+}
+
+/// Finds the module's block in a waybar config, which is either a single bar
+/// or a list of bars.
+pub(crate) fn user_config(
+    waybar_config: &str,
+    module: &str,
+) -> Result<UserConfig, Box<dyn std::error::Error>> {
+    let waybar_config: serde_json::Value = serde_json::from_str(&jsonc::to_json(waybar_config))?;
+    let bars = match waybar_config {
+        serde_json::Value::Array(bars) => bars,
+        bar => vec![bar],
+    };
+    let block = bars
+        .into_iter()
+        .find_map(|mut bar| bar.get_mut(module).map(serde_json::Value::take))
+        .ok_or_else(|| format!("No \"{}\" block in the waybar config", module))?;
+    Ok(serde_json::from_value(block)?)
+}
+
+/// One line per workspace: its label markup, a tab and its CSS classes. Each
+/// window icon follows on an indented line of its own, in the same format.
+fn render(config: &Config, ws_info: &[WorkspaceInfo]) -> String {
+    let mut lines = String::new();
+    for info in ws_info {
+        lines.push_str(&format!(
+            "{}\t{}\n",
+            format_workspace_label(config, info),
+            workspace_states(info).join(" ")
+        ));
+        for piece in workspace_label_pieces(config, info) {
+            if let IconPiece::Window {
+                markup, classes, ..
+            } = piece
+            {
+                lines.push_str(&format!("  {}\t{}\n", markup, classes.join(" ")));
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_config() {
+        let waybar_config = r#"[
+            { "modules-left": ["clock"] },
+            {
+                // The second bar has the module
+                "cffi/niri-workspaces-enhanced": { "format": "{index}", },
+            },
+        ]"#;
+        let uc = user_config(waybar_config, DEFAULT_MODULE).unwrap();
+        assert_eq!(uc.format.as_deref(), Some("{index}"));
+        assert!(user_config(waybar_config, "cffi/other").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let uc = user_config(
+            r#"{ "cffi/niri-workspaces-enhanced": {
+                "format": "{index}{separator}{window-icons}",
+                "window-icons": { "firefox": "F" },
+                "window-icon-format": { "focused": "<b>{icon}</b>", "default": "{icon}" },
+            } }"#,
+            DEFAULT_MODULE,
+        )
+        .unwrap();
+        let config = Config::from_user(&uc);
+        let workspaces = r#"[
+            { "id": 1, "idx": 1, "name": null, "output": "DP-1", "is_urgent": false,
+              "is_active": true, "is_focused": true, "active_window_id": 10 },
+            { "id": 2, "idx": 2, "name": null, "output": "DP-1", "is_urgent": false,
+              "is_active": false, "is_focused": false, "active_window_id": null }
+        ]"#;
+        let windows = r#"[
+            { "id": 10, "title": "Mozilla Firefox", "app_id": "firefox", "pid": 100,
+              "workspace_id": 1, "is_focused": true, "is_floating": false,
+              "is_urgent": false, "layout": {
+                "pos_in_scrolling_layout": [1, 1], "tile_size": [800.0, 600.0],
+                "window_size": [800, 600], "tile_pos_in_workspace_view": [0.0, 0.0],
+                "window_offset_in_tile": [0.0, 0.0] } }
+        ]"#;
+        let update = build_update(
+            &config,
+            &serde_json::from_str::<Vec<niri_ipc::Workspace>>(workspaces).unwrap(),
+            serde_json::from_str(windows).unwrap(),
+            HashMap::new(),
+            &EventState::default(),
+        );
+        let ws_info = displayed_workspaces(&config, update.workspaces, &update.outputs);
+        assert_eq!(
+            render(&config, &ws_info),
+            "1: <b>F</b>\tfocused active\n  <b>F</b>\twindow focused active app-firefox\n2\tempty\n"
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Recordings of niri's event stream (`record-events`), which can be replayed
+//! with `preview --replay` to reproduce rendering bugs without the original
+//! setup.
+//!
+//! A recording is a JSONL file with one entry per line:
+//!
+//! ```json
+//! {"time":1760000000.25,"event":{"WorkspaceActivated":{"id":2,"focused":true}}}
+//! {"time":1760000000.25,"reply":{"request":"Workspaces","response":{"Workspaces":[...]}}}
+//! ```
+
+use crate::{Config, EventState, Update, apply_event, merge_window_icons, update_workspaces};
+use niri_ipc::{Event, Request, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Record {
+    /// Seconds since the Unix epoch
+    time: f64,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+enum Entry {
+    Event(Event),
+    /// niri's reply to one of the requests made for an update
+    Reply {
+        request: Request,
+        response: Response,
+    },
+    /// `window-icons-file` was reloaded
+    WindowIcons(HashMap<String, String>),
+}
+
+/// Appends entries to a recording. Failures to write are logged, so a full
+/// disk doesn't take the module down with it.
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn event(&self, event: &Event) {
+        self.write(Entry::Event(event.clone()));
+    }
+
+    pub fn reply(&self, request: &Request, response: &Response) {
+        self.write(Entry::Reply {
+            request: request.clone(),
+            response: response.clone(),
+        });
+    }
+
+    pub fn window_icons(&self, icons: &HashMap<String, String>) {
+        self.write(Entry::WindowIcons(icons.clone()));
+    }
+
+    fn write(&self, entry: Entry) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |time| time.as_secs_f64());
+        let mut line = match serde_json::to_string(&Record { time, entry }) {
+            Ok(line) => line,
+            Err(err) => {
+                log::error!("Failed to serialize recording entry: {}", err);
+                return;
+            }
+        };
+        line.push('\n');
+        // Written at once, so lines stay whole
+        if let Err(err) = (&self.file).write_all(line.as_bytes()) {
+            log::error!("Failed to write recording: {}", err);
+        }
+    }
+}
+
+/// Parses a recording, skipping a trailing line cut off mid-write.
+pub fn parse(contents: &str) -> Result<Vec<Record>, Box<dyn std::error::Error>> {
+    let lines: Vec<&str> = contents.lines().filter(|line| !line.is_empty()).collect();
+    let mut records = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            Err(_) if i + 1 == lines.len() => break,
+            Err(err) => return Err(format!("Line {}: {}", i + 1, err).into()),
+        }
+    }
+    Ok(records)
+}
+
+/// Feeds a recording through the same steps as niri's live event stream,
+/// sending an update once the replies to all of its requests have been read.
+/// Events only update the tracked state, since bursts of them share one
+/// update. Replies must answer the requests in the order they're made.
+pub fn replay(
+    mut config: Config,
+    records: &[Record],
+    tx: &async_channel::Sender<Update>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = EventState::default();
+    let mut replies = VecDeque::new();
+    for record in records {
+        match &record.entry {
+            Entry::Event(event) => {
+                apply_event(&mut state, event);
+            }
+            Entry::Reply { request, response } => {
+                replies.push_back((request.clone(), response.clone()));
+                let mut used = 0;
+                let mut incomplete = false;
+                let mut request =
+                    |wanted: Request| -> Result<Response, Box<dyn std::error::Error>> {
+                        let Some((request, response)) = replies.get(used) else {
+                            incomplete = true;
+                            return Err("Incomplete update".into());
+                        };
+                        // Request isn't PartialEq
+                        if format!("{:?}", request) != format!("{:?}", wanted) {
+                            return Err(format!(
+                                "Recorded reply to {:?} where {:?} was requested",
+                                request, wanted
+                            )
+                            .into());
+                        }
+                        used += 1;
+                        Ok(response.clone())
+                    };
+                match update_workspaces(&config, tx, &mut request, &mut state) {
+                    Ok(()) => {
+                        replies.drain(..used);
+                    }
+                    // The rest of the replies come later
+                    Err(_) if incomplete => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            Entry::WindowIcons(file_icons) => {
+                config.window_icons = merge_window_icons(file_icons, &config.user_window_icons);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UserConfig;
+
+    fn workspaces(focused: u64) -> Response {
+        Response::Workspaces(
+            [1, 2]
+                .into_iter()
+                .map(|id| niri_ipc::Workspace {
+                    id,
+                    idx: id as u8,
+                    name: None,
+                    output: Some("DP-1".to_string()),
+                    is_urgent: false,
+                    is_active: id == focused,
+                    is_focused: id == focused,
+                    active_window_id: None,
+                })
+                .collect(),
+        )
+    }
+
+    fn reply(request: Request, response: Response) -> Entry {
+        Entry::Reply { request, response }
+    }
+
+    fn replies(focused: u64) -> Vec<Entry> {
+        vec![
+            reply(Request::Workspaces, workspaces(focused)),
+            reply(Request::Windows, Response::Windows(Vec::new())),
+            reply(Request::Outputs, Response::Outputs(HashMap::new())),
+        ]
+    }
+
+    fn recording(entries: Vec<Entry>) -> String {
+        entries
+            .into_iter()
+            .map(|entry| serde_json::to_string(&Record { time: 1.0, entry }).unwrap() + "\n")
+            .collect()
+    }
+
+    fn replay_all(contents: &str) -> Result<Vec<Update>, Box<dyn std::error::Error>> {
+        let config = Config::from_user(&UserConfig::default());
+        let (tx, rx) = async_channel::unbounded();
+        replay(config, &parse(contents)?, &tx)?;
+        drop(tx);
+        Ok(std::iter::from_fn(|| rx.try_recv().ok()).collect())
+    }
+
+    fn focused(update: &Update) -> Vec<u64> {
+        update
+            .workspaces
+            .iter()
+            .filter(|info| info.is_focused)
+            .map(|info| info.id)
+            .collect()
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut entries = replies(1);
+        entries.push(Entry::Event(Event::WorkspaceActivated {
+            id: 2,
+            focused: true,
+        }));
+        entries.extend(replies(2));
+        // Bursts of events share one update
+        entries.push(Entry::Event(Event::KeyboardLayoutSwitched { idx: 1 }));
+        entries.push(Entry::Event(Event::OverviewOpenedOrClosed {
+            is_open: true,
+        }));
+        entries.push(Entry::Event(Event::WindowClosed { id: 10 }));
+        entries.extend(replies(2));
+
+        let updates = replay_all(&recording(entries)).unwrap();
+        assert_eq!(updates.len(), 3);
+        assert_eq!(focused(&updates[0]), vec![1]);
+        assert_eq!(focused(&updates[1]), vec![2]);
+        assert!(updates[1].workspaces.iter().any(|info| info.is_previous));
+        assert!(updates[2].overview_open);
+    }
+
+    #[test]
+    fn test_parse() {
+        let contents = recording(replies(1));
+        assert_eq!(parse(&contents).unwrap().len(), 3);
+
+        // A line cut off by a crash is ignored
+        let truncated = format!("{}{{\"time\":1.0,\"rep", contents);
+        assert_eq!(parse(&truncated).unwrap().len(), 3);
+
+        let corrupt = format!("{{}}\n{}", contents);
+        assert!(parse(&corrupt).is_err());
+    }
+
+    #[test]
+    fn test_replay_truncated_update() {
+        // Recording stopped in the middle of the second update
+        let mut entries = replies(1);
+        entries.extend(replies(2));
+        entries.pop();
+        let updates = replay_all(&recording(entries)).unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(focused(&updates[0]), vec![1]);
+    }
+
+    #[test]
+    fn test_replay_mismatched_reply() {
+        // A reply to a request the module no longer makes first
+        let mut entries = vec![reply(Request::FocusedOutput, Response::FocusedOutput(None))];
+        entries.extend(replies(1));
+        let err = replay_all(&recording(entries)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Recorded reply to FocusedOutput where Workspaces was requested"
+        );
+    }
+
+    #[test]
+    fn test_replay_skips_identical_updates() {
+        let mut entries = replies(1);
+        entries.push(Entry::Event(Event::WindowLayoutsChanged {
+            changes: Vec::new(),
+        }));
+        entries.extend(replies(1));
+        assert_eq!(replay_all(&recording(entries)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_recorder() {
+        let path = std::env::temp_dir().join(format!("recording-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let recorder = Recorder::open(&path).unwrap();
+        recorder.reply(&Request::Windows, &Response::Windows(Vec::new()));
+        recorder.event(&Event::OverviewOpenedOrClosed { is_open: false });
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records = parse(&contents).unwrap();
+        assert!(matches!(
+            records[..],
+            [
+                Record {
+                    entry: Entry::Reply {
+                        request: Request::Windows,
+                        response: Response::Windows(_),
+                    },
+                    ..
+                },
+                Record {
+                    entry: Entry::Event(Event::OverviewOpenedOrClosed { is_open: false }),
+                    ..
+                },
+            ]
+        ));
+        assert!(records[0].time > 0.0);
+    }
+}
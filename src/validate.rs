@@ -0,0 +1,709 @@
+//! Checks the user config for mistakes that deserializing alone accepts:
+//! unknown keys, unknown placeholders, invalid markup and out of range numbers.
+
+use crate::actions::MouseAction;
+use crate::{UserConfig, UserHideEmpty, UserPersistentWorkspace, UserPersistentWorkspaces};
+use std::collections::BTreeMap;
+
+/// Keys of the module's own config.
+const CONFIG_KEYS: &[&str] = &[
+    "format",
+    "window-icons",
+    "window-icons-file",
+    "window-icon-default",
+    "window-icon-format",
+    "icon-separator",
+    "column-separator",
+    "label-separator",
+    "column-format",
+    "column-format-single",
+    "floating-format",
+    "floating-position",
+    "display",
+    "minimap-scale",
+    "hide-empty",
+    "persistent-workspaces",
+    "sort-by",
+    "group-by-output",
+    "output-header-format",
+    "drag-window-focus",
+    "accessible-format",
+    "overview-format",
+    "click-focused",
+    "workspace-actions",
+    "window-icon-actions",
+    "record-events",
+    "update-debounce",
+    "urgent-timeout",
+    "urgent-pulse",
+];
+
+/// Keys handled by waybar itself, which are passed to the module as well.
+const WAYBAR_KEYS: &[&str] = &[
+    "module_path",
+    "actions",
+    "align",
+    "cursor",
+    "expand",
+    "format-alt",
+    "justify",
+    "max-length",
+    "menu",
+    "menu-actions",
+    "menu-file",
+    "min-length",
+    "on-click",
+    "on-click-backward",
+    "on-click-forward",
+    "on-click-middle",
+    "on-click-right",
+    "on-double-click",
+    "on-scroll-down",
+    "on-scroll-left",
+    "on-scroll-right",
+    "on-scroll-up",
+    "on-triple-click",
+    "on-update",
+    "rotate",
+    "smooth-scrolling-threshold",
+    "tooltip",
+];
+
+const WINDOW_ICON_FORMAT_KEYS: &[&str] = &["focused", "urgent", "active", "floating", "default"];
+const HIDE_EMPTY_KEYS: &[&str] = &["keep-focused", "keep-named", "keep-trailing"];
+const PERSISTENT_WORKSPACE_KEYS: &[&str] = &["name", "spawn"];
+const MOUSE_ACTION_KEYS: &[&str] = &[
+    "on-click",
+    "on-shift-click",
+    "on-ctrl-click",
+    "on-click-middle",
+    "on-click-right",
+    "on-scroll-up",
+    "on-scroll-down",
+];
+
+const NAMED_ACTIONS: &[&str] = &[
+    "focus",
+    "move-focused-window-here",
+    "close-window",
+    "toggle-overview",
+    "center-column",
+];
+
+const WORKSPACE_PLACEHOLDERS: &[&str] = &[
+    "index",
+    "name",
+    "index-and-name",
+    "value",
+    "separator",
+    "window-icons",
+];
+const ICON_PLACEHOLDERS: &[&str] = &["icon", "title", "app_id", "pid", "index"];
+const GROUP_PLACEHOLDERS: &[&str] = &["icons", "count"];
+const OUTPUT_HEADER_PLACEHOLDERS: &[&str] = &["output", "output-make", "output-model"];
+const ACCESSIBLE_PLACEHOLDERS: &[&str] = &[
+    "index",
+    "name",
+    "index-and-name",
+    "value",
+    "windows",
+    "state",
+];
+
+/// Returns a description of every problem found in `uc`. `check_markup`
+/// validates pango markup, returning the parser's error.
+pub fn check(uc: &UserConfig, check_markup: impl Fn(&str) -> Result<(), String>) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    check_keys(&mut problems, "", &uc.unknown, CONFIG_KEYS, WAYBAR_KEYS);
+    if let Some(formats) = &uc.window_icon_formats {
+        check_keys(
+            &mut problems,
+            "window-icon-format.",
+            &formats.unknown,
+            WINDOW_ICON_FORMAT_KEYS,
+            &[],
+        );
+    }
+    if let Some(UserHideEmpty::Options(options)) = &uc.hide_empty {
+        check_keys(
+            &mut problems,
+            "hide-empty.",
+            &options.unknown,
+            HIDE_EMPTY_KEYS,
+            &[],
+        );
+    }
+    let persistent_workspaces: Vec<(String, &UserPersistentWorkspace)> =
+        match &uc.persistent_workspaces {
+            Some(UserPersistentWorkspaces::All(entries)) => entries
+                .iter()
+                .map(|entry| ("persistent-workspaces.".to_string(), entry))
+                .collect(),
+            Some(UserPersistentWorkspaces::PerOutput(outputs)) => outputs
+                .iter()
+                .flat_map(|(output, entries)| {
+                    entries
+                        .iter()
+                        .map(move |entry| (format!("persistent-workspaces.{}.", output), entry))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+    for (prefix, entry) in persistent_workspaces {
+        if let UserPersistentWorkspace::Full { unknown, .. } = entry {
+            check_keys(
+                &mut problems,
+                &prefix,
+                unknown,
+                PERSISTENT_WORKSPACE_KEYS,
+                &[],
+            );
+        }
+    }
+    for (key, mouse_actions) in [
+        ("workspace-actions.", &uc.workspace_actions),
+        ("window-icon-actions.", &uc.window_icon_actions),
+    ] {
+        if let Some(mouse_actions) = mouse_actions {
+            check_keys(
+                &mut problems,
+                key,
+                &mouse_actions.unknown,
+                MOUSE_ACTION_KEYS,
+                &[],
+            );
+            for (event, action) in mouse_actions.iter() {
+                let Some(action) = action else { continue };
+                if MouseAction::from_user(action).is_some() {
+                    continue;
+                }
+                let mut hint = did_you_mean(action.trim(), NAMED_ACTIONS);
+                if hint.is_empty() {
+                    hint = ", shell commands need the \"exec:\" prefix".to_string();
+                }
+                problems.push(format!(
+                    "{}{}: unknown action {:?}{}",
+                    key, event, action, hint
+                ));
+            }
+        }
+    }
+
+    if let Some(scale) = uc.minimap_scale
+        && scale <= 0.0
+    {
+        problems.push(format!(
+            "minimap-scale must be greater than 0, not {}, using the default",
+            scale
+        ));
+    }
+    if let Some(timeout) = uc.urgent_timeout
+        && timeout < 0.0
+    {
+        problems.push(format!(
+            "urgent-timeout can't be negative, not {}, ignoring it",
+            timeout
+        ));
+    }
+    if uc.urgent_pulse == Some(0) {
+        problems.push("urgent-pulse must be at least 1, ignoring it".to_string());
+    }
+
+    let mut formats: Vec<(String, &str, &[&str], Option<&str>)> = Vec::new();
+    for (key, format) in [
+        ("format", &uc.format),
+        ("overview-format", &uc.overview_format),
+    ] {
+        if let Some(format) = format {
+            formats.push((
+                key.to_string(),
+                format,
+                WORKSPACE_PLACEHOLDERS,
+                Some("{window-icons}"),
+            ));
+        }
+    }
+    if let Some(user_formats) = &uc.window_icon_formats {
+        for (state, format) in user_formats.iter() {
+            if let Some(format) = format {
+                if !format.contains("{icon}") {
+                    problems.push(format!(
+                        "window-icon-format.{} must contain \"{{icon}}\", using the default",
+                        state
+                    ));
+                }
+                formats.push((
+                    format!("window-icon-format.{}", state),
+                    format,
+                    ICON_PLACEHOLDERS,
+                    None,
+                ));
+            }
+        }
+    }
+    for (key, format) in [
+        ("column-format", &uc.column_format),
+        ("column-format-single", &uc.column_format_single),
+        ("floating-format", &uc.floating_format),
+    ] {
+        if let Some(format) = format {
+            formats.push((key.to_string(), format, GROUP_PLACEHOLDERS, Some("{icons}")));
+        }
+    }
+    if let Some(format) = &uc.output_header_format {
+        formats.push((
+            "output-header-format".to_string(),
+            format,
+            OUTPUT_HEADER_PLACEHOLDERS,
+            None,
+        ));
+    }
+    for (key, separator) in [
+        ("icon-separator", &uc.icon_separator),
+        ("column-separator", &uc.column_separator),
+        ("label-separator", &uc.label_separator),
+    ] {
+        if let Some(separator) = separator {
+            formats.push((key.to_string(), separator, &[], None));
+        }
+    }
+
+    // The accessible name is plain text, so only its placeholders are checked
+    if let Some(format) = &uc.accessible_format {
+        check_placeholders(
+            &mut problems,
+            "accessible-format",
+            format,
+            ACCESSIBLE_PLACEHOLDERS,
+        );
+    }
+
+    for (key, format, placeholders, split_at) in formats {
+        check_placeholders(&mut problems, &key, format, placeholders);
+
+        let Some(split_at) = split_at else {
+            let markup = without_placeholders(format);
+            if let Err(err) = check_markup(&markup) {
+                problems.push(format!("{}: invalid markup {:?}: {}", key, markup, err));
+            }
+            continue;
+        };
+        if splits_cleanly(format, split_at, &check_markup) {
+            continue;
+        }
+        if check_markup(&without_placeholders(format)).is_ok() {
+            problems.push(format!(
+                "{}: markup can't span {}, using the default",
+                key, split_at
+            ));
+            continue;
+        }
+        for part in format.split(split_at) {
+            let markup = without_placeholders(part);
+            if let Err(err) = check_markup(&markup) {
+                problems.push(format!(
+                    "{}: invalid markup {:?}: {}, using the default",
+                    key, markup, err
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Whether the markup on each side of every `split_at` in `format` is valid on
+/// its own. Icons are rendered as separate widgets, so markup spanning them
+/// can't be rendered.
+pub fn splits_cleanly(
+    format: &str,
+    split_at: &str,
+    check_markup: impl Fn(&str) -> Result<(), String>,
+) -> bool {
+    format
+        .split(split_at)
+        .all(|part| check_markup(&without_placeholders(part)).is_ok())
+}
+
+/// `format` with its placeholders replaced by plain text. Placeholders are
+/// substituted with escaped text, so they can't affect whether the markup is
+/// valid.
+fn without_placeholders(format: &str) -> String {
+    placeholders_in(format).fold(format.to_string(), |markup, p| {
+        markup.replace(&format!("{{{}}}", p), "x")
+    })
+}
+
+fn check_placeholders(problems: &mut Vec<String>, key: &str, format: &str, known: &[&str]) {
+    for placeholder in placeholders_in(format) {
+        if !known.contains(&placeholder) {
+            problems.push(format!(
+                "{}: unknown placeholder {{{}}}{}",
+                key,
+                placeholder,
+                did_you_mean(placeholder, known)
+            ));
+        }
+    }
+}
+
+/// Names of the `{placeholder}`s in `format`. Braces around anything but
+/// lowercase letters, `-` and `_` are not considered placeholders.
+fn placeholders_in(format: &str) -> impl Iterator<Item = &str> {
+    format.split('{').skip(1).filter_map(|part| {
+        let (name, _) = part.split_once('}')?;
+        let is_placeholder = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c == '-' || c == '_');
+        is_placeholder.then_some(name)
+    })
+}
+
+fn check_keys(
+    problems: &mut Vec<String>,
+    prefix: &str,
+    unknown: &BTreeMap<String, serde_json::Value>,
+    known: &[&str],
+    ignored: &[&str],
+) {
+    for key in unknown.keys() {
+        if !ignored.contains(&key.as_str()) {
+            problems.push(format!(
+                "unknown key \"{}{}\"{}",
+                prefix,
+                key,
+                did_you_mean(key, known)
+            ));
+        }
+    }
+}
+
+/// Suggests the candidate closest to `input`, if any is close enough to be a
+/// likely typo. Words in a different order (e.g. "on-middle-click" for
+/// "on-click-middle") count as an exact match.
+fn did_you_mean(input: &str, candidates: &[&str]) -> String {
+    let max_distance = (input.len() / 3).max(2);
+    let words = |key: &str| {
+        let mut words: Vec<String> = key.split('-').map(str::to_string).collect();
+        words.sort();
+        words
+    };
+    candidates
+        .iter()
+        .map(|candidate| {
+            let distance = if words(input) == words(candidate) {
+                0
+            } else {
+                edit_distance(input, candidate)
+            };
+            (distance, candidate)
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| format!(", did you mean \"{}\"?", candidate))
+        .unwrap_or_default()
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Checks markup with pango, the same parser GTK labels use.
+pub fn pango_markup(markup: &str) -> Result<(), String> {
+    waybar_cffi::gtk::pango::parse_markup(markup, '\0')
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn check_json(json: &str) -> Vec<String> {
+        let uc: UserConfig = serde_json::from_str(json).unwrap();
+        check(&uc, pango_markup)
+    }
+
+    /// Keys of `value` once serialized. `None` fields serialize as null, so
+    /// this is every field of a config type.
+    fn field_keys(value: impl serde::Serialize) -> BTreeSet<String> {
+        match serde_json::to_value(value).unwrap() {
+            serde_json::Value::Object(map) => map.keys().cloned().collect(),
+            value => panic!("{} is not an object", value),
+        }
+    }
+
+    fn keys(keys: &[&str]) -> BTreeSet<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_known_keys_are_fields() {
+        use crate::*;
+
+        // Unknown fields would be reported as typos, and known keys that
+        // aren't fields would never be
+        assert_eq!(field_keys(UserConfig::default()), keys(CONFIG_KEYS));
+        assert_eq!(
+            field_keys(UserWindowIconFormats::default()),
+            keys(WINDOW_ICON_FORMAT_KEYS)
+        );
+        assert_eq!(
+            field_keys(UserHideEmptyOptions::default()),
+            keys(HIDE_EMPTY_KEYS)
+        );
+        assert_eq!(
+            field_keys(actions::UserMouseActions::default()),
+            keys(MOUSE_ACTION_KEYS)
+        );
+        assert_eq!(
+            field_keys(persistent_workspace()),
+            keys(PERSISTENT_WORKSPACE_KEYS)
+        );
+    }
+
+    fn persistent_workspace() -> crate::UserPersistentWorkspace {
+        crate::UserPersistentWorkspace::Full {
+            name: String::new(),
+            spawn: None,
+            unknown: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        assert_eq!(
+            check_json(
+                r#"{
+                    "module_path": "x.so",
+                    "window-icon": {},
+                    "hide-empty": { "keep-focussed": true },
+                    "workspace-actions": { "on-middle-click": "focus" },
+                    "persistent-workspaces": { "DP-1": [{ "name": "code", "spwan": ["code"] }] },
+                    "bogus-option-name": 1
+                }"#
+            ),
+            vec![
+                "unknown key \"bogus-option-name\"",
+                "unknown key \"window-icon\", did you mean \"window-icons\"?",
+                "unknown key \"hide-empty.keep-focussed\", did you mean \"keep-focused\"?",
+                "unknown key \"persistent-workspaces.DP-1.spwan\", did you mean \"spawn\"?",
+                "unknown key \"workspace-actions.on-middle-click\", did you mean \"on-click-middle\"?",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_actions() {
+        assert_eq!(
+            check_json(
+                r#"{
+                    "workspace-actions": {
+                        "on-click": "focsu",
+                        "on-click-middle": "notify-send {name}",
+                        "on-click-right": "exec: notify-send {name}"
+                    },
+                    "window-icon-actions": { "on-scroll-up": " close-window " }
+                }"#
+            ),
+            vec![
+                "workspace-actions.on-click: unknown action \"focsu\", did you mean \"focus\"?",
+                "workspace-actions.on-click-middle: unknown action \"notify-send {name}\", shell commands need the \"exec:\" prefix",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_placeholders() {
+        assert_eq!(
+            check_json(
+                r#"{
+                    "format": "{idx} {window-icons}",
+                    "window-icon-format": { "default": "{icon} {titel}" },
+                    "column-format": "[{icons}] {COUNT} ${HOME}",
+                    "accessible-format": "{index} {windows} {focused}"
+                }"#
+            ),
+            vec![
+                "accessible-format: unknown placeholder {focused}",
+                "format: unknown placeholder {idx}, did you mean \"index\"?",
+                "window-icon-format.default: unknown placeholder {titel}, did you mean \"title\"?",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_markup() {
+        let problems = check_json(
+            r#"{
+                "format": "<b>{index}</b>{window-icons}",
+                "column-format": "<b>{icons}</b>",
+                "label-separator": "<b>"
+            }"#,
+        );
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert_eq!(
+            problems[0],
+            "column-format: markup can't span {icons}, using the default"
+        );
+        // The rest is glib's error, which is localized
+        assert!(
+            problems[1].starts_with("label-separator: invalid markup \"<b>\": "),
+            "{}",
+            problems[1]
+        );
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert_eq!(
+            check_json(r#"{ "minimap-scale": 0, "urgent-timeout": -1.5, "urgent-pulse": 0 }"#),
+            vec![
+                "minimap-scale must be greater than 0, not 0, using the default",
+                "urgent-timeout can't be negative, not -1.5, ignoring it",
+                "urgent-pulse must be at least 1, ignoring it",
+            ]
+        );
+        assert_eq!(
+            check_json(r#"{ "minimap-scale": 0.5, "urgent-timeout": 0, "urgent-pulse": 1 }"#),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_pango_markup() {
+        assert_eq!(
+            pango_markup("<b>1</b> <span foreground=\"red\">x</span>"),
+            Ok(())
+        );
+        assert!(pango_markup("Tom & Jerry").is_err());
+        assert!(pango_markup("<bold>").is_err());
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+
+    /// Shipped for editors to validate and autocomplete the module's config
+    const SCHEMA: &str = include_str!("../config.schema.json");
+
+    #[test]
+    fn test_schema_matches_config() {
+        use crate::*;
+        use serde_json::{Value, json};
+
+        let schema: Value = serde_json::from_str(SCHEMA).unwrap();
+        let schema_keys = |pointer: &str| -> BTreeSet<String> {
+            schema.pointer(pointer).unwrap()["properties"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect()
+        };
+        assert_eq!(schema_keys(""), field_keys(UserConfig::default()));
+        assert_eq!(
+            schema_keys("/properties/window-icon-format"),
+            field_keys(UserWindowIconFormats::default())
+        );
+        assert_eq!(
+            schema_keys("/properties/hide-empty/oneOf/1"),
+            field_keys(UserHideEmptyOptions::default())
+        );
+        assert_eq!(
+            schema_keys("/properties/workspace-actions"),
+            field_keys(actions::UserMouseActions::default())
+        );
+        assert_eq!(
+            schema_keys("/properties/window-icon-actions"),
+            field_keys(actions::UserMouseActions::default())
+        );
+        assert_eq!(
+            schema_keys("/definitions/persistentWorkspace/oneOf/1"),
+            field_keys(persistent_workspace())
+        );
+        let waybar_pattern = format!("^({})$", WAYBAR_KEYS.join("|"));
+        assert!(schema["patternProperties"].get(&waybar_pattern).is_some());
+
+        let defaults = [
+            ("format", json!(DEFAULT_WORKSPACE_FORMAT)),
+            (
+                "window-icon-format/properties/default",
+                json!(DEFAULT_FORMAT),
+            ),
+            ("icon-separator", json!(DEFAULT_ICON_SEPARATOR)),
+            ("label-separator", json!(DEFAULT_LABEL_SEPARATOR)),
+            ("column-format", json!(DEFAULT_COLUMN_FORMAT)),
+            ("column-format-single", json!(DEFAULT_COLUMN_FORMAT)),
+            ("accessible-format", json!(DEFAULT_ACCESSIBLE_FORMAT)),
+            ("minimap-scale", json!(DEFAULT_MINIMAP_SCALE)),
+            (
+                "hide-empty/oneOf/1/properties/keep-focused",
+                json!(HideEmpty::default().keep_focused),
+            ),
+            (
+                "hide-empty/oneOf/1/properties/keep-named",
+                json!(HideEmpty::default().keep_named),
+            ),
+            (
+                "hide-empty/oneOf/1/properties/keep-trailing",
+                json!(HideEmpty::default().keep_trailing),
+            ),
+        ];
+        for (key, default) in defaults {
+            let pointer = format!("/properties/{}/default", key);
+            assert_eq!(schema.pointer(&pointer), Some(&default), "{}", pointer);
+        }
+        let default = |key: &str| schema["properties"][key]["default"].clone();
+        assert_eq!(
+            serde_json::from_value::<FloatingPosition>(default("floating-position")).unwrap(),
+            FloatingPosition::default()
+        );
+        assert_eq!(
+            serde_json::from_value::<Display>(default("display")).unwrap(),
+            Display::default()
+        );
+        assert_eq!(
+            serde_json::from_value::<SortBy>(default("sort-by")).unwrap(),
+            SortBy::default()
+        );
+        assert_eq!(
+            serde_json::from_value::<ClickFocused>(default("click-focused")).unwrap(),
+            ClickFocused::default()
+        );
+
+        // Every enum value and every default must be accepted
+        let mut config = serde_json::Map::new();
+        for (key, property) in schema["properties"].as_object().unwrap() {
+            for value in property["enum"].as_array().into_iter().flatten() {
+                let single = json!({ key: value });
+                if let Err(err) = serde_json::from_value::<UserConfig>(single) {
+                    panic!("{} can't be {}: {}", key, value, err);
+                }
+            }
+            if let Some(default) = property.get("default") {
+                config.insert(key.clone(), default.clone());
+            }
+        }
+        let uc: UserConfig = serde_json::from_value(config.into()).unwrap();
+        assert_eq!(check(&uc, pango_markup), Vec::<String>::new());
+    }
+}